@@ -1,20 +1,29 @@
-use crate::builder::tgt::Target;
+use crate::builder::{
+    pkg_config,
+    sched::{self, BuildOptions},
+    state::BuildState,
+    tgt::Target,
+    timings,
+};
 use crate::global_config::GlobalConfig;
 use crate::utils::{
     self,
-    configs::{BuildConfig, TargetConfig},
+    configs::{BuildConfig, Define, Profile, TargetConfig},
     log::{log, LogLevel},
     package::Package,
 };
+use itertools::Itertools;
 use std::fs;
 use std::io::Write;
 use std::path::Path;
 use std::process::{Command, Stdio};
+use std::time::Instant;
 
 ///Cleans the local targets
 /// # Arguments
+/// * `build_config` - The local build configuration
 /// * `targets` - A vector of targets to clean
-pub fn clean(targets: &Vec<TargetConfig>) {
+pub fn clean(build_config: &BuildConfig, targets: &Vec<TargetConfig>) {
     if Path::new(".bld_cpp").exists() {
         fs::create_dir_all(".bld_cpp").unwrap_or_else(|why| {
             log(
@@ -23,56 +32,29 @@ pub fn clean(targets: &Vec<TargetConfig>) {
             );
         });
     }
-    if Path::new(Target::obj_dir()).exists() {
-        fs::remove_dir_all(Target::obj_dir()).unwrap_or_else(|why| {
-            log(
-                LogLevel::Error,
-                &format!("Could not remove object directory: {}", why),
-            );
-        });
-        log(LogLevel::Info, &format!("Cleaning: {}", Target::obj_dir()));
-    }
-    for target in targets {
-        //remove hashes
-        #[cfg(target_os = "windows")]
-        let hash_path = format!(".bld_cpp/{}.win32.hash", &target.name);
-        #[cfg(target_os = "linux")]
-        let hash_path = format!(".bld_cpp/{}.linux.hash", &target.name);
-        #[cfg(target_os = "android")]
-        let hash_path = format!(".bld_cpp/{}.linux.hash", &target.name);
-
-        if Path::new(&hash_path).exists() {
-            fs::remove_file(&hash_path).unwrap_or_else(|why| {
+    //the objects of the packages are removed as well, so they are compiled again
+    let obj_dirs = [
+        Target::obj_dir(&build_config.profile),
+        Target::package_obj_dir(&build_config.profile),
+    ];
+    for obj_dir in obj_dirs {
+        if Path::new(&obj_dir).exists() {
+            fs::remove_dir_all(&obj_dir).unwrap_or_else(|why| {
                 log(
                     LogLevel::Error,
-                    &format!("Could not remove hash file: {}", why),
+                    &format!("Could not remove object directory: {}", why),
                 );
             });
-            log(LogLevel::Info, &format!("Cleaning: {}", &hash_path));
+            log(LogLevel::Info, &format!("Cleaning: {}", &obj_dir));
         }
-        if Path::new(Target::build_dir()).exists() {
-            let mut bin_name = String::new();
-            bin_name.push_str(Target::build_dir());
-            bin_name.push('/');
-            bin_name.push_str(&target.name);
-            #[cfg(target_os = "windows")]
-            if target.typ == "exe" {
-                bin_name.push_str(".exe");
-            } else if target.typ == "dll" {
-                bin_name.push_str(".dll");
-            }
-            #[cfg(target_os = "linux")]
-            if target.typ == "exe" {
-                bin_name.push_str("");
-            } else if target.typ == "dll" {
-                bin_name.push_str(".so");
-            }
-            #[cfg(target_os = "android")]
-            if target.typ == "exe" {
-                bin_name.push_str("");
-            } else if target.typ == "dll" {
-                bin_name.push_str(".so");
-            }
+    }
+    for target in targets {
+        //forget what the target was built from
+        BuildState::remove_target(None, &target.name, &build_config.profile);
+        if Path::new(&Target::build_dir(&build_config.profile)).exists()
+            && target.typ != "interface"
+        {
+            let bin_name = Target::bin_path_for(target, &build_config.profile);
             if Path::new(&bin_name).exists() {
                 fs::remove_file(&bin_name).unwrap_or_else(|why| {
                     log(
@@ -93,16 +75,15 @@ pub fn clean(targets: &Vec<TargetConfig>) {
 
 ///Cleans the downloaded packages
 /// # Arguments
+/// * `build_config` - The local build configuration
 /// * `packages` - A vector of packages to clean
-pub fn clean_packages(packages: &Vec<Package>) {
+pub fn clean_packages(build_config: &BuildConfig, packages: &Vec<Package>) {
     for pack in packages {
         for target in &pack.target_configs {
-            #[cfg(target_os = "windows")]
-            let pack_bin_path = format!("{}/{}.dll", Target::build_dir(), &target.name);
-            #[cfg(target_os = "linux")]
-            let pack_bin_path = format!("{}/{}.so", Target::build_dir(), &target.name);
-            #[cfg(target_os = "android")]
-            let pack_bin_path = format!("{}/{}.so", Target::build_dir(), &target.name);
+            if target.typ == "interface" {
+                continue;
+            }
+            let pack_bin_path = Target::bin_path_for(target, &build_config.profile);
 
             if !Path::new(&pack_bin_path).exists() {
                 log(
@@ -138,14 +119,16 @@ pub fn clean_packages(packages: &Vec<Package>) {
 /// * `build_config` - The local build configuration
 /// * `targets` - A vector of targets to build
 /// * `gen_cc` - Whether to generate a compile_commands.json file
+/// * `options` - How many jobs to run at once
 pub fn build(
     build_config: &BuildConfig,
     targets: &Vec<TargetConfig>,
     gen_cc: bool,
     gen_vsc: bool,
     packages: &Vec<Package>,
+    options: &BuildOptions,
 ) {
-    if !Path::new("./.bld_cpp").exists() {
+    if !options.dry_run && !Path::new("./.bld_cpp").exists() {
         fs::create_dir(".bld_cpp").unwrap_or_else(|why| {
             log(
                 LogLevel::Error,
@@ -172,6 +155,7 @@ pub fn build(
     }
 
     if gen_vsc {
+        let vsc_start = Instant::now();
         let mut vsc_file = fs::OpenOptions::new()
             .append(true)
             .open(".vscode/c_cpp_properties.json")
@@ -183,13 +167,46 @@ pub fn build(
                 std::process::exit(1);
             });
 
-        let mut inc_dirs: Vec<String> = targets.iter().map(|t| t.include_dir.clone()).collect();
+        let mut inc_dirs: Vec<String> = Vec::new();
+        for target in targets {
+            inc_dirs.extend(target.include_dirs.iter().cloned());
+            inc_dirs.extend(target.public_include_dirs.iter().cloned());
+        }
+        for package in packages {
+            for target in &package.target_configs {
+                inc_dirs.extend(target.public_include_dirs.iter().cloned());
+            }
+        }
+        //pkg-config modules bring their own include dirs and defines
+        let mut pkg_cflags = Vec::new();
+        let package_targets = packages.iter().flat_map(|package| &package.target_configs);
+        for target in targets.iter().chain(package_targets) {
+            pkg_cflags.extend(pkg_config::resolve(&target.pkg_config, &target.name).cflags);
+        }
+        inc_dirs.extend(include_dirs_in(&pkg_cflags));
+        let inc_dirs = inc_dirs
+            .iter()
+            .unique()
+            .map(|inc_dir| json_string(inc_dir))
+            .join(",\n\t\t\t\t");
+
+        //the defines of every target, so IntelliSense sees all of them
+        let mut defines = build_config.defines.clone();
+        defines.extend(defines_in(&pkg_cflags));
+        for target in targets {
+            defines.extend(target.defines.iter().cloned());
+        }
         for package in packages {
             for target in &package.target_configs {
-                inc_dirs.push(target.include_dir.clone());
+                defines.extend(target.defines.iter().cloned());
             }
         }
-        let compiler_path: String = build_config.compiler.clone();
+        defines.extend(build_config.active_profile().defines);
+        let defines = Define::merge(defines)
+            .iter()
+            .map(|define| json_string(&define.to_macro()))
+            .join(",\n\t\t\t\t");
+        let compiler_path: String = build_config.cxx.clone();
         let mut intellimode: String = String::new();
         if compiler_path == "gcc" || compiler_path == "g++" {
             intellimode = "gcc-x64".to_string();
@@ -242,14 +259,12 @@ pub fn build(
         {{
             "name": "Win32",
             "includePath": [
-                "{}"
+                {}
             ],
             "defines": [
-                "_DEBUG",
-                "UNICODE",
-                "_UNICODE"
+                {}
             ],
-            "compilerPath": "{}",
+            "compilerPath": {},
             "cStandard": "{}",
             "cppStandard": "{}",
             "intelliSenseMode": "windows-{}"
@@ -257,8 +272,9 @@ pub fn build(
     ],
     "version": 4
 }}"#,
-            inc_dirs.join("\",\n\t\t\t\t\""),
-            compiler_path,
+            inc_dirs,
+            defines,
+            json_string(&compiler_path),
             cstandard,
             cppstandard,
             intellimode
@@ -267,7 +283,7 @@ pub fn build(
         #[cfg(target_os = "linux")]
         let compiler_path = Command::new("sh")
             .arg("-c")
-            .arg(&format!("which {}", &compiler_path))
+            .arg(format!("which {}", &compiler_path))
             .output()
             .expect("failed to execute process")
             .stdout;
@@ -282,14 +298,12 @@ pub fn build(
         {{
             "name": "Linux",
             "includePath": [
-                "{}"
+                {}
             ],
             "defines": [
-                "_DEBUG",
-                "UNICODE",
-                "_UNICODE"
+                {}
             ],
-            "compilerPath": "{}",
+            "compilerPath": {},
             "cStandard": "{}",
             "cppStandard": "{}",
             "intelliSenseMode": "linux-{}"
@@ -297,8 +311,9 @@ pub fn build(
     ],
     "version": 4
 }}"#,
-            inc_dirs.join("\",\n\t\t\t\t\""),
-            compiler_path,
+            inc_dirs,
+            defines,
+            json_string(&compiler_path),
             cstandard,
             cppstandard,
             intellimode
@@ -321,14 +336,12 @@ pub fn build(
         {{
             "name": "Linux",
             "includePath": [
-                "{}"
+                {}
             ],
             "defines": [
-                "_DEBUG",
-                "UNICODE",
-                "_UNICODE"
+                {}
             ],
-            "compilerPath": "{}",
+            "compilerPath": {},
             "cStandard": "{}",
             "cppStandard": "{}",
             "intelliSenseMode": "linux-{}"
@@ -336,8 +349,9 @@ pub fn build(
     ],
     "version": 4
 }}"#,
-            inc_dirs.join("\",\n\t\t\t\t\""),
-            compiler_path,
+            inc_dirs,
+            defines,
+            json_string(&compiler_path),
             cstandard,
             cppstandard,
             intellimode
@@ -353,12 +367,10 @@ pub fn build(
                 );
                 std::process::exit(1);
             });
+        timings::record_since("generate", "c_cpp_properties.json", vsc_start);
     }
 
-    for target in targets {
-        let mut tgt = Target::new(build_config, target, targets, packages);
-        tgt.build(gen_cc);
-    }
+    let built = sched::build(build_config, targets, packages, gen_cc, options);
     if gen_cc {
         let mut cc_file = fs::OpenOptions::new()
             .read(true)
@@ -376,9 +388,114 @@ pub fn build(
             std::process::exit(1);
         });
     }
+    //the timings of a failed build are written as well, to see how far it got
+    if !options.dry_run {
+        timings::write();
+    }
+    if !built {
+        std::process::exit(1);
+    }
     log(LogLevel::Info, "Build complete");
 }
 
+//returns the include dirs given by -I, -isystem and -idirafter flags
+fn include_dirs_in(flags: &[String]) -> Vec<String> {
+    let mut dirs = Vec::new();
+    let mut flags = flags.iter();
+    while let Some(flag) = flags.next() {
+        for prefix in ["-I", "-isystem", "-idirafter"] {
+            if let Some(dir) = flag.strip_prefix(prefix) {
+                //the dir can also be the next argument
+                if dir.is_empty() {
+                    dirs.extend(flags.next().cloned());
+                } else {
+                    dirs.push(dir.to_string());
+                }
+                break;
+            }
+        }
+    }
+    dirs
+}
+
+//returns the defines given by -D flags
+fn defines_in(flags: &[String]) -> Vec<Define> {
+    let mut defines = Vec::new();
+    let mut flags = flags.iter();
+    while let Some(flag) = flags.next() {
+        let define = match flag.strip_prefix("-D") {
+            Some("") => flags.next().cloned(),
+            Some(define) => Some(define.to_string()),
+            None => None,
+        };
+        if let Some(define) = define {
+            let (name, value) = match define.split_once('=') {
+                Some((name, value)) => (name.to_string(), Some(value.to_string())),
+                None => (define, None),
+            };
+            defines.push(Define { name, value });
+        }
+    }
+    defines
+}
+
+//returns the string as a json string, quoted and escaped
+fn json_string(string: &str) -> String {
+    serde_json::to_string(string).unwrap()
+}
+
+///Picks the exe target to run
+/// # Arguments
+/// * `targets` - A vector of targets
+/// * `name` - The name of the exe target, may be omitted if there is only one
+pub fn select_exe_target<'a>(targets: &'a [TargetConfig], name: Option<&str>) -> &'a TargetConfig {
+    let exe_targets: Vec<&TargetConfig> = targets.iter().filter(|x| x.typ == "exe").collect();
+    let exe_names = exe_targets
+        .iter()
+        .map(|x| x.name.as_str())
+        .collect::<Vec<&str>>()
+        .join(", ");
+    if exe_targets.is_empty() {
+        log(LogLevel::Error, "No executable targets to run");
+        std::process::exit(1);
+    }
+    match name {
+        Some(name) => {
+            let exe_target = exe_targets.iter().find(|x| x.name == name);
+            if let Some(exe_target) = exe_target {
+                return exe_target;
+            }
+            if targets.iter().any(|x| x.name == name) {
+                log(
+                    LogLevel::Error,
+                    &format!("Target {} is not an executable", name),
+                );
+            } else {
+                log(LogLevel::Error, &format!("No target named {}", name));
+            }
+            log(
+                LogLevel::Error,
+                &format!("Available executables: {}", exe_names),
+            );
+            std::process::exit(1);
+        }
+        None => {
+            if exe_targets.len() > 1 {
+                log(
+                    LogLevel::Error,
+                    "Multiple executable targets found, pick one with --run <name>",
+                );
+                log(
+                    LogLevel::Error,
+                    &format!("Available executables: {}", exe_names),
+                );
+                std::process::exit(1);
+            }
+            exe_targets[0]
+        }
+    }
+}
+
 ///Runs the exe target
 /// # Arguments
 /// * `build_config` - The local build configuration
@@ -392,7 +509,8 @@ pub fn run(
     targets: &Vec<TargetConfig>,
     packages: &Vec<Package>,
 ) {
-    let trgt = Target::new(build_config, exe_target, targets, packages);
+    let state = BuildState::load(&build_config.profile);
+    let trgt = Target::new(build_config, exe_target, targets, packages, &state, &[]);
     if !Path::new(&trgt.bin_path).exists() {
         log(
             LogLevel::Error,
@@ -465,12 +583,7 @@ pub fn init(project_name: &str, is_c: Option<bool>, config: GlobalConfig) {
         std::process::exit(1);
     }
 
-    #[cfg(target_os = "windows")]
-    let config_file = project_name.to_owned() + "/config_win32.toml";
-    #[cfg(target_os = "linux")]
-    let config_file = project_name.to_owned() + "/config_linux.toml";
-    #[cfg(target_os = "android")]
-    let config_file = project_name.to_owned() + "/config_linux.toml";
+    let config_file = project_name.to_owned() + "/builder_cpp.toml";
 
     if Path::new(&config_file).exists() {
         log(LogLevel::Error, &format!("{} already exists", config_file));
@@ -481,6 +594,7 @@ pub fn init(project_name: &str, is_c: Option<bool>, config: GlobalConfig) {
     let mut config_file = fs::OpenOptions::new()
         .write(true)
         .create(true)
+        .truncate(true)
         .open(config_file)
         .unwrap_or_else(|why| {
             log(
@@ -506,9 +620,9 @@ pub fn init(project_name: &str, is_c: Option<bool>, config: GlobalConfig) {
             std::process::exit(1);
         }
     };
-    let sample_cpp_config = format!("[build]\ncompiler = \"{}\"\n\n[[targets]]\nname = \"main\"\nsrc = \"./src/\"\ninclude_dir = \"./src/include/\"\ntype = \"exe\"\ncflags = \"-g -Wall -Wextra\"\nlibs = \"\"\ndeps = [\"\"]\n", cpp_compiler);
+    let sample_cpp_config = format!("[build]\ncc = \"{}\"\ncxx = \"{}\"\n\n[[targets]]\nname = \"main\"\nsrc = \"./src/\"\ninclude_dir = \"./src/include/\"\ntype = \"exe\"\ncflags = \"-g -Wall -Wextra\"\nlibs = \"\"\ndeps = []\n", c_compiler, cpp_compiler);
 
-    let sample_c_config = format!("[build]\ncompiler = \"{}\"\n\n[[targets]]\nname = \"main\"\nsrc = \"./src/\"\ninclude_dir = \"./src/include/\"\ntype = \"exe\"\ncflags = \"-g -Wall -Wextra\"\nlibs = \"\"\ndeps = [\"\"]\n", c_compiler);
+    let sample_c_config = format!("[build]\ncc = \"{}\"\ncxx = \"{}\"\n\n[[targets]]\nname = \"main\"\nsrc = \"./src/\"\ninclude_dir = \"./src/include/\"\ntype = \"exe\"\ncflags = \"-g -Wall -Wextra\"\nlibs = \"\"\ndeps = []\n", c_compiler, cpp_compiler);
 
     let sample_config = match is_c {
         Some(true) => sample_c_config,
@@ -575,6 +689,7 @@ pub fn init(project_name: &str, is_c: Option<bool>, config: GlobalConfig) {
         let mut main_file = fs::OpenOptions::new()
             .write(true)
             .create(true)
+            .truncate(true)
             .open(&main_path)
             .unwrap_or_else(|why| {
                 log(
@@ -633,6 +748,7 @@ pub fn init(project_name: &str, is_c: Option<bool>, config: GlobalConfig) {
         let mut gitignore_file = fs::OpenOptions::new()
             .write(true)
             .create(true)
+            .truncate(true)
             .open(&gitignore_path)
             .unwrap_or_else(|why| {
                 log(
@@ -655,6 +771,7 @@ pub fn init(project_name: &str, is_c: Option<bool>, config: GlobalConfig) {
     let mut readme_file = fs::OpenOptions::new()
         .write(true)
         .create(true)
+        .truncate(true)
         .open(project_name.to_owned() + "/README.md")
         .unwrap_or_else(|why| {
             log(
@@ -676,6 +793,7 @@ pub fn init(project_name: &str, is_c: Option<bool>, config: GlobalConfig) {
     let mut license_file = fs::OpenOptions::new()
         .write(true)
         .create(true)
+        .truncate(true)
         .open(project_name.to_owned() + "/LICENSE")
         .unwrap_or_else(|why| {
             log(
@@ -718,26 +836,42 @@ pub fn init_project(project_name: String, is_c: Option<bool>, config: GlobalConf
     std::process::exit(0);
 }
 
-pub fn parse_config() -> (BuildConfig, Vec<TargetConfig>, Vec<Package>) {
-    #[cfg(target_os = "linux")]
-    let (build_config, targets) = utils::configs::parse_config("./config_linux.toml", true);
-    #[cfg(target_os = "windows")]
-    let (build_config, targets) = utils::configs::parse_config("./config_win32.toml", true);
-    #[cfg(target_os = "android")]
-    let (build_config, targets) = utils::configs::parse_config("./config_linux.toml", true);
+///Parses the local config and the packages it uses
+/// # Arguments
+/// * `profile` - The profile to build with
+pub fn parse_config(profile: &str) -> (BuildConfig, Vec<TargetConfig>, Vec<Package>) {
+    let config_path = utils::configs::config_path(".");
+    //the profile names a directory of .bld_cpp, which clean removes
+    if !Profile::is_valid_name(profile) {
+        log(
+            LogLevel::Error,
+            &format!(
+                "Invalid profile name: {}, profile names can not be empty or contain path separators or ..",
+                profile
+            ),
+        );
+        std::process::exit(1);
+    }
+    let (mut build_config, targets) = utils::configs::parse_config(&config_path, true);
+
+    if !build_config.profiles.contains_key(profile) {
+        log(LogLevel::Error, &format!("No profile named {}", profile));
+        let mut profile_names = build_config.profiles.keys().cloned().collect::<Vec<_>>();
+        profile_names.sort();
+        log(
+            LogLevel::Error,
+            &format!("Available profiles: {}", profile_names.join(", ")),
+        );
+        std::process::exit(1);
+    }
+    build_config.profile = profile.to_string();
 
-    let mut num_exe = 0;
-    let mut exe_target: Option<&TargetConfig> = None;
     if targets.is_empty() {
         log(LogLevel::Error, "No targets in config");
         std::process::exit(1);
     } else {
-        //Allow only one exe and set it as the exe_target
         for target in &targets {
-            if target.typ == "exe" {
-                num_exe += 1;
-                exe_target = Some(target);
-            } else if target.typ == "dll" {
+            if target.typ == "dll" {
                 // Check if the dll target name starts with lib
                 if !target.name.starts_with("lib") {
                     log(
@@ -762,20 +896,13 @@ pub fn parse_config() -> (BuildConfig, Vec<TargetConfig>, Vec<Package>) {
         }
     }
 
-    if num_exe != 1 || exe_target.is_none() {
-        log(
-            LogLevel::Error,
-            "Exactly one executable target must be specified",
-        );
-        std::process::exit(1);
-    }
-
-    #[cfg(target_os = "linux")]
-    let packages = Package::parse_packages("./config_linux.toml");
-    #[cfg(target_os = "android")]
-    let packages = Package::parse_packages("./config_linux.toml");
-    #[cfg(target_os = "windows")]
-    let packages = Package::parse_packages("./config_win32.toml");
+    let packages = Package::parse_packages(&build_config, profile);
+    let package_targets = packages
+        .iter()
+        .flat_map(|package| package.target_configs.iter())
+        .map(|target| target.name.clone())
+        .collect::<Vec<String>>();
+    TargetConfig::check_deps(&targets, &package_targets, &config_path);
 
     (build_config, targets, packages)
 }
@@ -802,9 +929,9 @@ pub fn pre_gen_vsc() {
     }
 }
 
-pub fn clean_packages_wrapper(packages: &Vec<Package>) {
+pub fn clean_packages_wrapper(build_config: &BuildConfig, packages: &Vec<Package>) {
     log(LogLevel::Log, "Cleaning packages...");
-    clean_packages(packages);
+    clean_packages(build_config, packages);
 }
 
 pub fn update_packages(packages: &Vec<Package>) {
@@ -1,3 +1,9 @@
 //! This module contains the buiild related functions
+pub mod cmds;
+pub mod depfile;
+pub mod pkg_config;
+pub mod sched;
 pub mod src;
+pub mod state;
 pub mod tgt;
+pub mod timings;
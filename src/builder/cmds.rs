@@ -0,0 +1,425 @@
+//Splits the flags of the config into the arguments the compiler and linker are
+//run with, and compares the command an output was last built with to its
+//current one, so changing the compiler or the flags tells what changed when it rebuilds
+use crate::utils::log::{log, LogLevel};
+use std::collections::HashMap;
+use std::io;
+use std::iter::Peekable;
+use std::process::{Command, Output};
+use std::str::Chars;
+use std::sync::{Mutex, OnceLock};
+
+//the output of every command substituted into the flags, by command, so each
+//runs once per build however many targets and files use it
+static SUBSTITUTIONS: OnceLock<Mutex<HashMap<String, String>>> = OnceLock::new();
+
+//collects the arguments while the flags are read
+#[derive(Default)]
+struct Splitter {
+    args: Vec<String>,
+    arg: String,
+    in_arg: bool,
+}
+
+impl Splitter {
+    //ends the argument being read, if any
+    fn end_arg(&mut self) {
+        if self.in_arg {
+            self.args.push(std::mem::take(&mut self.arg));
+            self.in_arg = false;
+        }
+    }
+
+    //adds the value of an unquoted substitution, which is split on whitespace
+    fn push_fields(&mut self, value: &str) {
+        if value.starts_with(char::is_whitespace) {
+            self.end_arg();
+        }
+        for (i, field) in value.split_whitespace().enumerate() {
+            if i > 0 {
+                self.end_arg();
+            }
+            self.arg.push_str(field);
+            self.in_arg = true;
+        }
+        if value.ends_with(char::is_whitespace) {
+            self.end_arg();
+        }
+    }
+}
+
+/// Splits flags into arguments the way a shell does
+/// Quotes and backslashes are removed, `$VAR` and `${VAR}` are replaced by the
+/// environment variable and `` `cmd` `` and `$(cmd)` by the output of the command,
+/// which is run once per build
+/// # Arguments
+/// * `flags` - The flags, like cflags or libs
+pub fn split_args(flags: &str) -> Vec<String> {
+    let mut splitter = Splitter::default();
+    let mut chars = flags.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => splitter.end_arg(),
+            '\'' => {
+                splitter.in_arg = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => splitter.arg.push(c),
+                        None => unterminated(flags),
+                    }
+                }
+            }
+            '"' => {
+                splitter.in_arg = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => splitter.arg.push(c),
+                            Some('\n') => {}
+                            Some(c) => {
+                                splitter.arg.push('\\');
+                                splitter.arg.push(c);
+                            }
+                            None => unterminated(flags),
+                        },
+                        Some('`') => {
+                            let cmd = read_until(&mut chars, '`', flags);
+                            splitter.arg.push_str(&substitute(&cmd));
+                        }
+                        Some('$') => splitter.arg.push_str(&expand(&mut chars, flags)),
+                        Some(c) => splitter.arg.push(c),
+                        None => unterminated(flags),
+                    }
+                }
+            }
+            '\\' => {
+                splitter.in_arg = true;
+                match chars.next() {
+                    Some('\n') | None => {}
+                    Some(c) => splitter.arg.push(c),
+                }
+            }
+            '`' => {
+                let cmd = read_until(&mut chars, '`', flags);
+                splitter.push_fields(&substitute(&cmd));
+            }
+            '$' => {
+                let value = expand(&mut chars, flags);
+                splitter.push_fields(&value);
+            }
+            c => {
+                splitter.in_arg = true;
+                splitter.arg.push(c);
+            }
+        }
+    }
+    splitter.end_arg();
+    splitter.args
+}
+
+//reads up to the closing character, which is consumed
+fn read_until(chars: &mut Peekable<Chars>, end: char, flags: &str) -> String {
+    let mut read = String::new();
+    loop {
+        match chars.next() {
+            Some(c) if c == end => return read,
+            Some(c) => read.push(c),
+            None => unterminated(flags),
+        }
+    }
+}
+
+//returns the value of what follows a $, a command, an environment variable
+//or the $ itself if neither follows
+fn expand(chars: &mut Peekable<Chars>, flags: &str) -> String {
+    match chars.peek() {
+        Some('(') => {
+            chars.next();
+            let mut cmd = String::new();
+            let mut depth = 0;
+            loop {
+                match chars.next() {
+                    Some(')') if depth == 0 => break,
+                    Some(c) => {
+                        match c {
+                            '(' => depth += 1,
+                            ')' => depth -= 1,
+                            _ => {}
+                        }
+                        cmd.push(c);
+                    }
+                    None => unterminated(flags),
+                }
+            }
+            substitute(&cmd)
+        }
+        Some('{') => {
+            chars.next();
+            let name = read_until(chars, '}', flags);
+            std::env::var(name).unwrap_or_default()
+        }
+        Some(c) if c.is_ascii_alphanumeric() || *c == '_' => {
+            let mut name = String::new();
+            while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                name.push(c);
+            }
+            std::env::var(name).unwrap_or_default()
+        }
+        _ => "$".to_string(),
+    }
+}
+
+//exits on flags whose quotes or substitutions are not closed
+fn unterminated(flags: &str) -> ! {
+    log(
+        LogLevel::Error,
+        &format!("Unterminated quote or substitution in: {}", flags),
+    );
+    std::process::exit(1);
+}
+
+//returns the output of a command substituted into the flags, without the
+//trailing newlines, running the command only the first time
+fn substitute(cmd: &str) -> String {
+    let mut substitutions = SUBSTITUTIONS.get_or_init(Default::default).lock().unwrap();
+    if let Some(output) = substitutions.get(cmd) {
+        return output.clone();
+    }
+    log(LogLevel::Info, &format!("Running subcmd: {}", cmd));
+    let output = Command::new("sh").arg("-c").arg(cmd).output();
+    let stdout = match output {
+        Ok(output) if output.status.success() => {
+            String::from_utf8_lossy(&output.stdout).to_string()
+        }
+        Ok(output) => {
+            log(
+                LogLevel::Error,
+                &format!("Failed to execute subcmd: {}", cmd),
+            );
+            log(
+                LogLevel::Error,
+                &format!("  Stderr: {}", String::from_utf8_lossy(&output.stderr)),
+            );
+            std::process::exit(1);
+        }
+        Err(why) => {
+            log(
+                LogLevel::Error,
+                &format!("Failed to execute subcmd: {}: {}", cmd, why),
+            );
+            std::process::exit(1);
+        }
+    };
+    let stdout = stdout.trim_end_matches('\n').to_string();
+    substitutions.insert(cmd.to_string(), stdout.clone());
+    stdout
+}
+
+/// Joins arguments into a command line, quoting those a shell would split
+/// or expand, used to show and record commands
+/// # Arguments
+/// * `args` - The program followed by its arguments
+pub fn join_args(args: &[String]) -> String {
+    args.iter()
+        .map(|arg| {
+            let plain = !arg.is_empty()
+                && arg
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-_+=/.,:@%^".contains(c));
+            if plain {
+                arg.clone()
+            } else {
+                format!("'{}'", arg.replace('\'', "'\\''"))
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Runs a program with its arguments, without going through a shell
+/// # Arguments
+/// * `args` - The program followed by its arguments
+pub fn execute(args: &[String]) -> io::Result<Output> {
+    let (program, args) = args
+        .split_first()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "empty command"))?;
+    Command::new(program).args(args).output()
+}
+
+/// Describes how a command differs from the one recorded in the last build
+/// like "flags changed: -O0 → -O2"
+/// Both commands are split like join_args wrote them, so an argument with a
+/// space in it is compared as one
+/// # Arguments
+/// * `old` - The recorded command
+/// * `new` - The current command
+pub fn describe_change(old: &str, new: &str) -> String {
+    let old_args = split_args(old);
+    let new_args = split_args(new);
+    if old_args.first() != new_args.first() {
+        let program = |args: &[String]| join_args(args.first().map_or(&[], std::slice::from_ref));
+        return format!(
+            "compiler changed: {} → {}",
+            program(&old_args),
+            program(&new_args)
+        );
+    }
+    if old_args == new_args {
+        return "flags changed quoting or spacing".to_string();
+    }
+    let removed = args_missing_from(&old_args, &new_args);
+    let added = args_missing_from(&new_args, &old_args);
+    if removed.is_empty() && added.is_empty() {
+        return "flags changed order".to_string();
+    }
+    let or_none = |args: Vec<String>| {
+        if args.is_empty() {
+            "(none)".to_string()
+        } else {
+            join_args(&args)
+        }
+    };
+    format!("flags changed: {} → {}", or_none(removed), or_none(added))
+}
+
+//returns the arguments of args that are not in other, an argument given twice
+//in args and once in other is returned once
+fn args_missing_from(args: &[String], other: &[String]) -> Vec<String> {
+    let mut other = other.to_vec();
+    let mut missing = Vec::new();
+    for arg in args {
+        match other.iter().position(|x| x == arg) {
+            Some(i) => {
+                other.remove(i);
+            }
+            None => missing.push(arg.clone()),
+        }
+    }
+    missing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //returns the strings as owned arguments
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn splits_like_a_shell() {
+        let cases: [(&str, &[&str]); 8] = [
+            ("g++  -O2\t-Wall ", &["g++", "-O2", "-Wall"]),
+            ("-I'my dir' -DNAME=\"a b\"", &["-Imy dir", "-DNAME=a b"]),
+            ("-DQ='\"x\"'", &["-DQ=\"x\""]),
+            ("\"a\\\"b\" c\\ d", &["a\"b", "c d"]),
+            ("'' \"\"", &["", ""]),
+            ("-Da\\\nb", &["-Dab"]),
+            ("\"\\n\"", &["\\n"]),
+            ("'$HOME' \\$HOME $", &["$HOME", "$HOME", "$"]),
+        ];
+        for (flags, expected) in cases {
+            assert_eq!(split_args(flags), args(expected), "{}", flags);
+        }
+    }
+
+    #[test]
+    fn expands_variables_and_commands() {
+        std::env::set_var("BUILDER_CPP_TEST_FLAGS", "-O2  -g");
+        assert_eq!(
+            split_args("$BUILDER_CPP_TEST_FLAGS -Wall"),
+            args(&["-O2", "-g", "-Wall"])
+        );
+        assert_eq!(
+            split_args("\"${BUILDER_CPP_TEST_FLAGS}\" x$BUILDER_CPP_TEST_UNSET"),
+            args(&["-O2  -g", "x"])
+        );
+        assert_eq!(
+            split_args("`echo -la -lb` -I$(echo inc)/x \"$(echo 'c d')\""),
+            args(&["-la", "-lb", "-Iinc/x", "c d"])
+        );
+    }
+
+    #[test]
+    fn joins_and_splits_back() {
+        let cases = [
+            args(&["g++", "-c", "src/main.cpp", "-o", "obj/main.o"]),
+            args(&["cc", "-Imy dir", "-DNAME=\"a b\"", "it's", ""]),
+            args(&[
+                "cc",
+                "$HOME",
+                "`id`",
+                "$(id)",
+                "a\\b",
+                "tab\there",
+                "*.c",
+                "~",
+            ]),
+            args(&[
+                "cc",
+                "-DLINES=a\nb",
+                "#x",
+                "a;b",
+                "a|b",
+                "a&b",
+                "(x)",
+                "{x}",
+            ]),
+        ];
+        for case in cases {
+            assert_eq!(split_args(&join_args(&case)), case, "{}", join_args(&case));
+        }
+        assert_eq!(
+            join_args(&args(&["g++", "-Imy dir", "it's"])),
+            "g++ '-Imy dir' 'it'\\''s'"
+        );
+    }
+
+    #[test]
+    fn describes_changes() {
+        let cases = [
+            (
+                "g++ -c a.cpp",
+                "clang++ -c a.cpp",
+                "compiler changed: g++ → clang++",
+            ),
+            (
+                "g++ -O0 -c a.cpp",
+                "g++ -O2 -c a.cpp",
+                "flags changed: -O0 → -O2",
+            ),
+            (
+                "g++ -c a.cpp",
+                "g++ -c a.cpp -g",
+                "flags changed: (none) → -g",
+            ),
+            (
+                "g++ -g -g -c a.cpp",
+                "g++ -g -c a.cpp",
+                "flags changed: -g → (none)",
+            ),
+            ("g++ -g -Wall", "g++ -Wall -g", "flags changed order"),
+            (
+                "g++ -DA=1",
+                "g++ '-DA=1'",
+                "flags changed quoting or spacing",
+            ),
+            (
+                "g++ '-Imy dir' -c a.cpp",
+                "g++ '-Imy other dir' -c a.cpp",
+                "flags changed: '-Imy dir' → '-Imy other dir'",
+            ),
+            (
+                "'/opt/my cc/g++' -c a.cpp",
+                "g++ -c a.cpp",
+                "compiler changed: '/opt/my cc/g++' → g++",
+            ),
+        ];
+        for (old, new, expected) in cases {
+            assert_eq!(describe_change(old, new), expected, "{} → {}", old, new);
+        }
+    }
+}
@@ -0,0 +1,148 @@
+//Reads the make style dependency files written by the compiler with -MMD -MF
+//to find the headers every source file includes
+use std::fs;
+
+/// Parses a dependency file written by the compiler
+/// Returns the headers the source depends on, without the source itself
+/// # Arguments
+/// * `path` - The path to the dependency file
+/// * `src_path` - The path to the source file the dependency file is for
+pub fn parse_depfile(path: &str, src_path: &str) -> Option<Vec<String>> {
+    let contents = fs::read_to_string(path).ok()?;
+    //rules can be continued on the next line with a backslash
+    let contents = contents.replace("\\\r\n", " ").replace("\\\n", " ");
+    let rule = contents.lines().next()?;
+    let (_, prerequisites) = rule.split_once(": ")?;
+
+    let mut deps = Vec::new();
+    let mut dep = String::new();
+    let mut chars = prerequisites.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            //spaces in paths are escaped with a backslash
+            '\\' if chars.peek() == Some(&' ') => {
+                dep.push(' ');
+                chars.next();
+            }
+            '$' if chars.peek() == Some(&'$') => {
+                dep.push('$');
+                chars.next();
+            }
+            ' ' | '\t' => {
+                if !dep.is_empty() {
+                    deps.push(std::mem::take(&mut dep));
+                }
+            }
+            _ => dep.push(c),
+        }
+    }
+    if !dep.is_empty() {
+        deps.push(dep);
+    }
+    let src_path = src_path.trim_start_matches("./");
+    deps.retain(|dep| dep.trim_start_matches("./") != src_path);
+    Some(deps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //writes contents to a dependency file of its own and parses it
+    fn parse(name: &str, contents: &str, src_path: &str) -> Option<Vec<String>> {
+        let path = std::env::temp_dir().join(format!(
+            "builder_cpp_depfile_{}_{}.d",
+            std::process::id(),
+            name
+        ));
+        let path = path.to_str().unwrap();
+        fs::write(path, contents).unwrap();
+        let deps = parse_depfile(path, src_path);
+        fs::remove_file(path).unwrap();
+        deps
+    }
+
+    #[test]
+    fn reads_the_headers_of_a_rule() {
+        let deps = parse(
+            "rule",
+            "obj/main.o: ./src/main.cpp src/a.h src/b.h\n",
+            "./src/main.cpp",
+        );
+        assert_eq!(
+            deps,
+            Some(vec!["src/a.h".to_string(), "src/b.h".to_string()])
+        );
+    }
+
+    #[test]
+    fn unescapes_spaces_and_dollars() {
+        let deps = parse(
+            "escapes",
+            "obj/my\\ main.o: src/my\\ main.cpp src/my\\ dir/a\\ b.h src/$$cost.h\n",
+            "src/my main.cpp",
+        );
+        assert_eq!(
+            deps,
+            Some(vec![
+                "src/my dir/a b.h".to_string(),
+                "src/$cost.h".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn joins_continued_lines() {
+        let deps = parse(
+            "continued",
+            "obj/main.o: src/main.cpp \\\n  src/a.h \\\r\n  src/b.h\\\n src/c.h\n",
+            "src/main.cpp",
+        );
+        assert_eq!(
+            deps,
+            Some(vec![
+                "src/a.h".to_string(),
+                "src/b.h".to_string(),
+                "src/c.h".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn reads_a_rule_with_several_targets() {
+        let deps = parse(
+            "targets",
+            "obj/main.o obj/main.d: src/main.cpp src/a.h\n",
+            "src/main.cpp",
+        );
+        assert_eq!(deps, Some(vec!["src/a.h".to_string()]));
+    }
+
+    #[test]
+    fn ignores_phony_header_rules() {
+        //-MP adds an empty rule for every header, after the real one
+        let deps = parse(
+            "phony",
+            "obj/main.o: src/main.cpp src/a.h \\\n src/b.h\n\nsrc/a.h:\n\nsrc/b.h:\n",
+            "src/main.cpp",
+        );
+        assert_eq!(
+            deps,
+            Some(vec!["src/a.h".to_string(), "src/b.h".to_string()])
+        );
+    }
+
+    #[test]
+    fn rejects_missing_and_malformed_files() {
+        assert_eq!(parse("empty", "", "src/main.cpp"), None);
+        assert_eq!(
+            parse("no_rule", "obj/main.o src/main.cpp\n", "src/main.cpp"),
+            None
+        );
+        let missing = std::env::temp_dir().join("builder_cpp_depfile_missing.d");
+        assert_eq!(
+            parse_depfile(missing.to_str().unwrap(), "src/main.cpp"),
+            None
+        );
+    }
+}
@@ -0,0 +1,96 @@
+//Queries pkg-config for the modules targets list in pkg_config, each module
+//once per build however many targets use it
+use super::cmds;
+use crate::utils::log::{log, LogLevel};
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::process::Command;
+use std::sync::{Mutex, OnceLock};
+
+//the flags of every module queried so far, by module
+static MODULES: OnceLock<Mutex<HashMap<String, PkgFlags>>> = OnceLock::new();
+
+/// The flags pkg-config gives for modules
+#[derive(Clone, Default)]
+pub struct PkgFlags {
+    /// The flags to compile with, like -I and -D flags
+    pub cflags: Vec<String>,
+    /// The flags to link with, like -L and -l flags
+    pub libs: Vec<String>,
+}
+
+/// Returns the flags of the modules a target lists in pkg_config
+/// Exits with the message of pkg-config if a module is missing or its
+/// version does not match
+/// # Arguments
+/// * `modules` - The modules, with an optional version like `freetype2 >= 2.10`
+/// * `target` - The name of the target, used in messages
+pub fn resolve(modules: &[String], target: &str) -> PkgFlags {
+    let mut flags = PkgFlags::default();
+    for module in modules {
+        let module_flags = query(module, target);
+        flags.cflags.extend(module_flags.cflags);
+        flags.libs.extend(module_flags.libs);
+    }
+    //modules often share include dirs
+    flags.cflags = flags.cflags.into_iter().unique().collect();
+    flags
+}
+
+//returns the flags of a module, asking pkg-config only the first time
+fn query(module: &str, target: &str) -> PkgFlags {
+    let mut modules = MODULES.get_or_init(Default::default).lock().unwrap();
+    if let Some(flags) = modules.get(module) {
+        return flags.clone();
+    }
+    let flags = PkgFlags {
+        cflags: cmds::split_args(&run(module, "--cflags", target)),
+        libs: cmds::split_args(&run(module, "--libs", target)),
+    };
+    log(
+        LogLevel::Info,
+        &format!(
+            "pkg-config {}: cflags: {} libs: {}",
+            module,
+            cmds::join_args(&flags.cflags),
+            cmds::join_args(&flags.libs)
+        ),
+    );
+    modules.insert(module.to_string(), flags.clone());
+    flags
+}
+
+//runs pkg-config for a module and returns what it printed
+//the program can be replaced with the PKG_CONFIG environment variable
+fn run(module: &str, what: &str, target: &str) -> String {
+    let program = std::env::var("PKG_CONFIG").unwrap_or("pkg-config".to_string());
+    let output = Command::new(&program)
+        .arg("--print-errors")
+        .arg(what)
+        .arg(module)
+        .output()
+        .unwrap_or_else(|why| {
+            log(
+                LogLevel::Error,
+                &format!(
+                    "Could not run {} for module {} of target {}: {}",
+                    program, module, target, why
+                ),
+            );
+            std::process::exit(1);
+        });
+    if !output.status.success() {
+        log(
+            LogLevel::Error,
+            &format!(
+                "pkg-config module {} of target {} is not available",
+                module, target
+            ),
+        );
+        for line in String::from_utf8_lossy(&output.stderr).lines() {
+            log(LogLevel::Error, &format!("  {}", line));
+        }
+        std::process::exit(1);
+    }
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
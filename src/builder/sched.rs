@@ -0,0 +1,573 @@
+//Builds the targets of the project and of its packages as one graph of compile
+//and link jobs run on a single pool of workers, so independent targets compile
+//at the same time and every target is built once
+use super::src::Src;
+use super::state::BuildState;
+use super::tgt::Target;
+use super::timings::{self, Event};
+use crate::utils::{
+    configs::{BuildConfig, TargetConfig},
+    log::{log, LogLevel},
+    package::Package,
+};
+use colored::Colorize;
+use indicatif::{ProgressBar, ProgressStyle};
+use itertools::Itertools;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+//how often a worker held back by the load average checks it again
+const LOAD_POLL: Duration = Duration::from_millis(500);
+
+/// How the jobs of the build are run
+#[derive(Default)]
+pub struct BuildOptions {
+    /// The most jobs run at once, the number of cpus if not set
+    pub jobs: Option<usize>,
+    /// No new job starts while the load average is at or above it,
+    /// unless nothing is running
+    pub load_average: Option<f64>,
+    /// Whether to carry on with the jobs that do not depend on a failed one
+    pub keep_going: bool,
+    /// Whether to only print the commands that would be run
+    pub dry_run: bool,
+    /// Whether to print why every source is compiled
+    pub explain: bool,
+}
+
+//a unit of work of the build graph
+enum Job {
+    //compiles a source of a target
+    Compile { target: usize, src: Src },
+    //links a target once its sources are compiled and its deps are linked
+    Link { target: usize },
+}
+
+impl Job {
+    //returns the target the job belongs to
+    fn target(&self) -> usize {
+        match self {
+            Job::Compile { target, .. } | Job::Link { target } => *target,
+        }
+    }
+}
+
+//a job along with the jobs waiting for it
+struct Node {
+    job: Job,
+    dependants: Vec<usize>,
+    deps: usize,
+}
+
+//what the compile jobs of a target produced, read by its link job
+#[derive(Default)]
+struct Compiled {
+    srcs: Vec<(String, Option<Vec<String>>)>,
+    warns: Vec<String>,
+}
+
+//when a job ran and on which worker, counted from 1
+struct Span {
+    start: Instant,
+    end: Instant,
+    worker: usize,
+}
+
+//how many jobs may run at once, in total and for every target, and the load
+//average above which no more are started
+struct Limits {
+    workers: usize,
+    max_jobs: Vec<Option<usize>>,
+    load_average: Option<f64>,
+    keep_going: bool,
+}
+
+/// Builds every target of the project and of its packages
+/// Returns false if a job failed, once every failure is listed
+/// # Arguments
+/// * `build_config` - The local build configuration
+/// * `targets` - The local targets, ordered so deps come first
+/// * `packages` - The packages of the project
+/// * `gen_cc` - Whether to write the compile_commands.json entries
+/// * `options` - How many jobs to run at once
+pub fn build(
+    build_config: &BuildConfig,
+    targets: &Vec<TargetConfig>,
+    packages: &Vec<Package>,
+    gen_cc: bool,
+    options: &BuildOptions,
+) -> bool {
+    //the state of every target is read from a single load of the database
+    let state = BuildState::load(&build_config.profile);
+    //package targets are created first, they are linked into every local target
+    let no_packages: Vec<Package> = Vec::new();
+    let mut tgts: Vec<Target> = Vec::new();
+    let mut groups = Vec::new();
+    for (i, package) in packages.iter().enumerate() {
+        let first = tgts.len();
+        for target_config in &package.target_configs {
+            let tgt = Target::new(
+                &package.build_config,
+                target_config,
+                &package.target_configs,
+                &no_packages,
+                &state,
+                &tgts[first..],
+            );
+            tgts.push(tgt);
+            groups.push(Some(i));
+        }
+    }
+    let first_local = tgts.len();
+    for target_config in targets {
+        let tgt = Target::new(
+            build_config,
+            target_config,
+            targets,
+            packages,
+            &state,
+            &tgts[first_local..],
+        );
+        tgts.push(tgt);
+        groups.push(None);
+    }
+
+    if !options.dry_run {
+        Target::remove_stale_targets(&state, &build_config.profile, &tgts);
+    }
+
+    let mut nodes: Vec<Node> = Vec::new();
+    let mut link_nodes: HashMap<(Option<usize>, String), usize> = HashMap::new();
+    let mut package_links = Vec::new();
+    for (i, tgt) in tgts.iter_mut().enumerate() {
+        let group = groups[i];
+        //package executables are not built
+        if group.is_some() && tgt.target_config.typ == "exe" {
+            continue;
+        }
+        let plan_start = Instant::now();
+        let srcs_to_build = tgt.plan(gen_cc, options);
+        timings::record(Event {
+            category: "check",
+            name: tgt.target_config.name.clone(),
+            target: Some(tgt.target_config.name.clone()),
+            lane: 0,
+            start: plan_start,
+            end: Instant::now(),
+        });
+        if tgt.target_config.typ == "interface" {
+            continue;
+        }
+        let link = nodes.len();
+        nodes.push(Node {
+            job: Job::Link { target: i },
+            dependants: Vec::new(),
+            deps: 0,
+        });
+        for (src, to_build) in tgt.srcs.iter().zip(srcs_to_build) {
+            if to_build {
+                nodes.push(Node {
+                    job: Job::Compile {
+                        target: i,
+                        src: src.clone(),
+                    },
+                    dependants: vec![link],
+                    deps: 0,
+                });
+                nodes[link].deps += 1;
+            }
+        }
+
+        let mut dep_links = Vec::new();
+        push_dep_names(tgt, &mut |name| {
+            if let Some(dep_link) = link_nodes.get(&(group, name.to_string())) {
+                dep_links.push(*dep_link);
+            }
+        });
+        if group.is_none() {
+            dep_links.extend(package_links.iter().copied());
+        }
+        for dep_link in dep_links.into_iter().unique() {
+            nodes[dep_link].dependants.push(link);
+            nodes[link].deps += 1;
+        }
+        link_nodes.insert((group, tgt.target_config.name.clone()), link);
+        if group.is_some() {
+            package_links.push(link);
+        }
+    }
+
+    let num_compiles = nodes
+        .iter()
+        .filter(|node| matches!(node.job, Job::Compile { .. }))
+        .count();
+    let progress_bar = ProgressBar::new(num_compiles as u64);
+    let log_level = std::env::var("BUILDER_CPP_LOG_LEVEL").unwrap_or("".to_string());
+    let show_progress =
+        num_compiles > 0 && !options.dry_run && !(log_level == "Info" || log_level == "Debug");
+    if show_progress {
+        let template = format!(
+            "    {}{}",
+            "Compiling :".cyan(),
+            "[{bar:40.}] {pos}/{len} ({percent}%) {msg}[{elapsed_precise}] "
+        );
+        progress_bar.set_style(
+            ProgressStyle::with_template(&template)
+                .unwrap()
+                .progress_chars("=>-"),
+        );
+    }
+
+    //a dry run prints the commands in the order they would run
+    let workers = match options.jobs {
+        _ if options.dry_run => 1,
+        Some(jobs) => jobs,
+        None => thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1),
+    };
+    if options.load_average.is_some() && current_load().is_none() {
+        log(
+            LogLevel::Warn,
+            "The load average can not be read on this system, --load-average is ignored",
+        );
+    }
+    log(LogLevel::Info, &format!("Running {} jobs at once", workers));
+    let limits = Limits {
+        workers,
+        max_jobs: tgts.iter().map(|tgt| tgt.target_config.max_jobs).collect(),
+        load_average: options.load_average,
+        keep_going: options.keep_going,
+    };
+
+    let tgts = tgts.into_iter().map(Mutex::new).collect::<Vec<_>>();
+    let compiled = (0..tgts.len())
+        .map(|_| Mutex::new(Compiled::default()))
+        .collect::<Vec<_>>();
+    //what failed along with the diagnostics of the compiler or linker
+    let failures: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+    //the binaries a dry run would link
+    let would_link: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    let (skipped, spans) = run(&nodes, &limits, |job| match job {
+        Job::Compile { target, src } if options.dry_run => {
+            log(LogLevel::Log, &src.compile_cmd);
+            let mut compiled = compiled[*target].lock().unwrap();
+            compiled.srcs.push((src.path.clone(), None));
+            true
+        }
+        Job::Link { target } if options.dry_run => {
+            let compiled = !compiled[*target].lock().unwrap().srcs.is_empty();
+            let tgt = tgts[*target].lock().unwrap();
+            let mut would_link = would_link.lock().unwrap();
+            if tgt.dry_run_link(compiled, &would_link) {
+                would_link.push(tgt.bin_path.clone());
+            }
+            true
+        }
+        Job::Compile { target, src } => {
+            if show_progress {
+                progress_bar.inc(1);
+            }
+            match src.build() {
+                Ok(warn) => {
+                    let includes = src.read_depfile();
+                    log(LogLevel::Info, &format!("Compiled: {}", src.path));
+                    let mut compiled = compiled[*target].lock().unwrap();
+                    compiled.srcs.push((src.path.clone(), includes));
+                    compiled.warns.extend(warn);
+                    true
+                }
+                Err(diagnostics) => {
+                    let failed = format!("Compiling {}", src.path);
+                    failures.lock().unwrap().push((failed, diagnostics));
+                    false
+                }
+            }
+        }
+        Job::Link { target } => {
+            let done = std::mem::take(&mut *compiled[*target].lock().unwrap());
+            let mut tgt = tgts[*target].lock().unwrap();
+            match tgt.finish(done.srcs, done.warns) {
+                Ok(()) => true,
+                Err(diagnostics) => {
+                    let failed = format!("Linking {}", tgt.target_config.name);
+                    failures.lock().unwrap().push((failed, diagnostics));
+                    false
+                }
+            }
+        }
+    });
+
+    //a dry run runs nothing worth timing
+    if !options.dry_run {
+        record_timings(&nodes, &spans, &tgts);
+    }
+
+    let mut failures = failures.into_inner().unwrap();
+    //the sources of a target that was not linked are not compiled again
+    if !options.dry_run {
+        for (target, compiled) in compiled.into_iter().enumerate() {
+            let done = compiled.into_inner().unwrap();
+            if done.srcs.is_empty() {
+                continue;
+            }
+            let pending = nodes
+                .iter()
+                .filter_map(|node| match &node.job {
+                    Job::Compile { target: of, src } if *of == target => Some(src.path.clone()),
+                    _ => None,
+                })
+                .filter(|path| !done.srcs.iter().any(|(compiled, _)| compiled == path))
+                .collect::<Vec<_>>();
+            let mut tgt = tgts[target].lock().unwrap();
+            if let Err(why) = tgt.save_compiled(done.srcs, &pending) {
+                let failed = format!("Saving the state of {}", tgt.target_config.name);
+                failures.push((failed, why));
+            }
+        }
+    }
+    if failures.is_empty() {
+        if show_progress {
+            progress_bar.finish();
+        }
+        return true;
+    }
+    progress_bar.abandon();
+    log(
+        LogLevel::Error,
+        &format!("Build failed, {} job(s) failed:", failures.len()),
+    );
+    for (failed, diagnostics) in &failures {
+        log(
+            LogLevel::Error,
+            &format!("{}\n{}", failed, diagnostics.trim_end()),
+        );
+    }
+    if options.keep_going {
+        let skipped_targets = nodes
+            .iter()
+            .zip(skipped)
+            .filter_map(|(node, skipped)| match node.job {
+                Job::Link { target } if skipped => {
+                    Some(tgts[target].lock().unwrap().target_config.name.clone())
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if !skipped_targets.is_empty() {
+            log(
+                LogLevel::Error,
+                &format!(
+                    "Not linked because of the failures: {}",
+                    skipped_targets.join(", ")
+                ),
+            );
+        }
+    } else {
+        log(
+            LogLevel::Error,
+            "Stopped at the first failure, use --keep-going to build everything that does not depend on it",
+        );
+    }
+    false
+}
+
+//records the jobs that ran and the critical path, if the build is being recorded
+fn record_timings(nodes: &[Node], spans: &[Option<Span>], tgts: &[Mutex<Target>]) {
+    if !timings::enabled() {
+        return;
+    }
+    let events = nodes
+        .iter()
+        .zip(spans)
+        .map(|(node, span)| {
+            let span = span.as_ref()?;
+            let target = tgts[node.job.target()]
+                .lock()
+                .unwrap()
+                .target_config
+                .name
+                .clone();
+            let (category, name) = match &node.job {
+                Job::Compile { src, .. } => ("compile", src.path.clone()),
+                Job::Link { .. } => ("link", target.clone()),
+            };
+            Some(Event {
+                category,
+                name,
+                target: Some(target),
+                lane: span.worker,
+                start: span.start,
+                end: span.end,
+            })
+        })
+        .collect::<Vec<Option<Event>>>();
+    for event in events.iter().flatten() {
+        timings::record(event.clone());
+    }
+
+    //the longest chain of jobs that ran, each waiting on the one before
+    let mut longest = vec![None; nodes.len()];
+    let mut first = None;
+    let mut length = Duration::ZERO;
+    for id in (0..nodes.len()).filter(|&id| events[id].is_some()) {
+        let chain_length = longest_chain(id, nodes, &events, &mut longest);
+        if first.is_none() || chain_length > length {
+            first = Some(id);
+            length = chain_length;
+        }
+    }
+    let mut critical_path = Vec::new();
+    while let Some(id) = first {
+        critical_path.extend(events[id].clone());
+        first = longest[id].and_then(|(_, next)| next);
+    }
+    timings::record_critical_path(critical_path);
+}
+
+//returns how long the longest chain of jobs starting at a job took, remembering
+//for every job the length of its chain and the job that comes next in it
+fn longest_chain(
+    id: usize,
+    nodes: &[Node],
+    events: &[Option<Event>],
+    longest: &mut Vec<Option<(Duration, Option<usize>)>>,
+) -> Duration {
+    if let Some((length, _)) = longest[id] {
+        return length;
+    }
+    let mut next = None;
+    let mut rest = Duration::ZERO;
+    for &dependant in &nodes[id].dependants {
+        let length = longest_chain(dependant, nodes, events, longest);
+        if events[dependant].is_some() && (next.is_none() || length > rest) {
+            next = Some(dependant);
+            rest = length;
+        }
+    }
+    let own = events[id].as_ref().map_or(Duration::ZERO, Event::duration);
+    longest[id] = Some((own + rest, next));
+    own + rest
+}
+
+//calls f with the names of the deps of a target, and of their deps
+fn push_dep_names(tgt: &Target, f: &mut impl FnMut(&str)) {
+    for dep in &tgt.dependant_libs {
+        f(&dep.target_config.name);
+        push_dep_names(dep, f);
+    }
+}
+
+//returns the load average of the last minute, if the system has one
+fn current_load() -> Option<f64> {
+    let loadavg = std::fs::read_to_string("/proc/loadavg").ok()?;
+    loadavg.split_whitespace().next()?.parse().ok()
+}
+
+//runs the jobs of the graph on a pool of workers, a job starts once all the
+//jobs it depends on are done and the limits allow it
+//run_job returns false if the job failed, then the jobs depending on it are
+//skipped, and no new job starts at all unless keep_going is set. Running jobs
+//are always waited for so no output is left half written
+//returns which jobs were skipped, and when every job that ran did
+fn run(
+    nodes: &[Node],
+    limits: &Limits,
+    run_job: impl Fn(&Job) -> bool + Sync,
+) -> (Vec<bool>, Vec<Option<Span>>) {
+    struct Queue {
+        ready: VecDeque<usize>,
+        deps: Vec<usize>,
+        done: usize,
+        running: usize,
+        running_per_target: Vec<usize>,
+        skipped: Vec<bool>,
+        spans: Vec<Option<Span>>,
+        failed: bool,
+    }
+    let queue = Mutex::new(Queue {
+        ready: (0..nodes.len()).filter(|&i| nodes[i].deps == 0).collect(),
+        deps: nodes.iter().map(|node| node.deps).collect(),
+        done: 0,
+        running: 0,
+        running_per_target: vec![0; limits.max_jobs.len()],
+        skipped: vec![false; nodes.len()],
+        spans: (0..nodes.len()).map(|_| None).collect(),
+        failed: false,
+    });
+    let wake = Condvar::new();
+    thread::scope(|scope| {
+        //every worker gets its own number, so the references are moved into it
+        let (queue, wake, run_job) = (&queue, &wake, &run_job);
+        for worker in 1..=limits.workers.max(1) {
+            scope.spawn(move || loop {
+                let id = {
+                    let mut queue = queue.lock().unwrap();
+                    loop {
+                        if queue.done == nodes.len() || (queue.failed && !limits.keep_going) {
+                            return;
+                        }
+                        //something always runs, so a high load can not stall the build
+                        let overloaded = !queue.ready.is_empty()
+                            && queue.running > 0
+                            && limits
+                                .load_average
+                                .is_some_and(|max| current_load().is_some_and(|load| load >= max));
+                        let next = queue.ready.iter().position(|&id| {
+                            let target = nodes[id].job.target();
+                            limits.max_jobs[target]
+                                .is_none_or(|max| queue.running_per_target[target] < max)
+                        });
+                        match next {
+                            Some(next) if !overloaded => {
+                                let id = queue.ready.remove(next).unwrap();
+                                queue.running += 1;
+                                queue.running_per_target[nodes[id].job.target()] += 1;
+                                break id;
+                            }
+                            _ if overloaded => {
+                                queue = wake.wait_timeout(queue, LOAD_POLL).unwrap().0;
+                            }
+                            _ => queue = wake.wait(queue).unwrap(),
+                        }
+                    }
+                };
+                let start = Instant::now();
+                let succeeded = run_job(&nodes[id].job);
+                let end = Instant::now();
+                let mut queue = queue.lock().unwrap();
+                queue.spans[id] = Some(Span { start, end, worker });
+                queue.done += 1;
+                queue.running -= 1;
+                queue.running_per_target[nodes[id].job.target()] -= 1;
+                if !succeeded {
+                    queue.failed = true;
+                    //everything waiting on the job, directly or not, is skipped
+                    let mut waiting = nodes[id].dependants.clone();
+                    while let Some(dependant) = waiting.pop() {
+                        if !queue.skipped[dependant] {
+                            queue.skipped[dependant] = true;
+                            queue.done += 1;
+                            waiting.extend(&nodes[dependant].dependants);
+                        }
+                    }
+                    wake.notify_all();
+                    continue;
+                }
+                //jobs that became ready go first, so links are not left
+                //waiting behind the compiles of other targets
+                for &dependant in &nodes[id].dependants {
+                    queue.deps[dependant] -= 1;
+                    if queue.deps[dependant] == 0 && !queue.skipped[dependant] {
+                        queue.ready.push_front(dependant);
+                    }
+                }
+                wake.notify_all();
+            });
+        }
+    });
+    let queue = queue.into_inner().unwrap();
+    (queue.skipped, queue.spans)
+}
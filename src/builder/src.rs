@@ -1,21 +1,26 @@
-use super::tgt::Target;
-use crate::hasher;
+use super::{cmds, depfile};
+use crate::hasher::{self, FileState};
 use crate::utils::{
     configs::{BuildConfig, TargetConfig},
     log::{log, LogLevel},
 };
 use std::collections::HashMap;
 use std::path::Path;
-use std::process::Command;
+use std::sync::Mutex;
+
+//the compilers already warned about, so each is reported once per build
+static UNKNOWN_COMPILERS: Mutex<Vec<String>> = Mutex::new(Vec::new());
 
 //Represents a source file
 //A single C or Cpp file
+#[derive(Clone)]
 pub struct Src {
     pub path: String,
     pub name: String,
     pub obj_name: String,
-    pub bin_path: String,
     pub dependant_includes: Vec<String>,
+    pub compile_args: Vec<String>,
+    pub compile_cmd: String,
 }
 
 impl Src {
@@ -24,24 +29,70 @@ impl Src {
         path: String,
         name: String,
         obj_name: String,
-        bin_path: String,
         dependant_includes: Vec<String>,
     ) -> Self {
         Self {
             path,
             name,
             obj_name,
-            bin_path,
             dependant_includes,
+            compile_args: Vec::new(),
+            compile_cmd: String::new(),
+        }
+    }
+
+    //returns true if the source file is C, C++ and CUDA files are compiled as C++
+    pub fn is_c(&self) -> bool {
+        self.path.ends_with(".c")
+    }
+
+    //returns the path of the dependency file the compiler writes for the source
+    pub fn depfile_path(&self) -> String {
+        format!("{}.d", self.obj_name)
+    }
+
+    //returns the headers the compiler found while building the source file
+    //the dependency file is removed once read
+    pub fn read_depfile(&self) -> Option<Vec<String>> {
+        let depfile_path = self.depfile_path();
+        let includes = depfile::parse_depfile(&depfile_path, &self.path);
+        let _ = std::fs::remove_file(&depfile_path);
+        includes
+    }
+
+    //returns how the compile command differs from the one the object was
+    //last built with, None if it is the same or was never recorded
+    pub fn command_change(&self, recorded_cmds: &HashMap<String, String>) -> Option<String> {
+        match recorded_cmds.get(&self.obj_name) {
+            Some(recorded_cmd) if *recorded_cmd != self.compile_cmd => {
+                Some(cmds::describe_change(recorded_cmd, &self.compile_cmd))
+            }
+            _ => None,
         }
     }
 
     //returns a tuple of a bool and a string
     //the bool is true if the source file needs to be built
     //the string is the reason the source file needs to be built
-    pub fn to_build(&self, path_hash: &HashMap<String, String>) -> (bool, String) {
-        if !Path::new(&self.bin_path).exists() {
-            let result = (true, format!("\tBinary does not exist: {}", &self.bin_path));
+    pub fn to_build(
+        &self,
+        path_hash: &HashMap<String, FileState>,
+        recorded_cmds: &HashMap<String, String>,
+    ) -> (bool, String) {
+        if !Path::new(&self.obj_name).exists() {
+            let result = (true, format!("\tObject does not exist: {}", &self.obj_name));
+            return result;
+        }
+
+        if !recorded_cmds.contains_key(&self.obj_name) {
+            let result = (
+                true,
+                format!("\tNo compile command recorded for: {}", &self.path),
+            );
+            return result;
+        }
+        if let Some(change) = self.command_change(recorded_cmds) {
+            let result = (true, format!("\t{}: {}", change, &self.path));
             return result;
         }
 
@@ -68,107 +119,87 @@ impl Src {
         )
     }
 
-    //builds the source file
-    pub fn build(
+    //returns the arguments that compile the source file
+    //flags are the ones shared by every source of the target, its include
+    //dirs, the cflags of the target, its deps and the profile and its defines
+    pub fn compile_command(
         &self,
         build_config: &BuildConfig,
         target_config: &TargetConfig,
-        dependant_libs: &Vec<Target>,
-    ) -> Option<String> {
-        let mut cmd = String::new();
-        cmd.push_str(&build_config.compiler);
-        cmd.push_str(" -c ");
-        cmd.push_str(&self.path);
-        cmd.push_str(" -o ");
-        cmd.push_str(&self.obj_name);
-        cmd.push_str(" -I");
-        cmd.push_str(&target_config.include_dir);
-        cmd.push(' ');
-
-        for dependant_lib in dependant_libs {
-            cmd.push_str("-I");
-            cmd.push_str(dependant_lib.target_config.include_dir.as_str());
-            cmd.push(' ');
-        }
-
-        if !build_config.packages.is_empty() {
-            for package in &build_config.packages {
-                cmd.push_str("-I");
-                cmd.push_str(&format!(
-                    ".bld_cpp/includes/{} ",
-                    &package
-                        .split_whitespace()
-                        .next()
-                        .unwrap()
-                        .split('/')
-                        .last()
-                        .unwrap()
-                        .replace(',', "")
-                ));
-                cmd.push(' ');
+        flags: &[String],
+    ) -> Vec<String> {
+        //the compiler can come with a launcher like ccache
+        let compiler = build_config.compiler_for(self.is_c());
+        let mut args = cmds::split_args(compiler);
+        args.push("-c".to_string());
+        args.push(self.path.clone());
+        args.push("-o".to_string());
+        args.push(self.obj_name.clone());
+        //the compiler lists the headers the source includes
+        args.push("-MMD".to_string());
+        args.push("-MF".to_string());
+        args.push(self.depfile_path());
+        args.extend(flags.iter().cloned());
+        if BuildConfig::is_gcc_like(compiler) {
+            args.push(format!(
+                "-std={}",
+                target_config.standard_for(build_config, self.is_c())
+            ));
+        } else {
+            let mut warned = UNKNOWN_COMPILERS.lock().unwrap();
+            if !warned.iter().any(|warned| warned == compiler) {
+                warned.push(compiler.to_string());
+                log(
+                    LogLevel::Warn,
+                    &format!(
+                        "Unknown compiler: {}, no standard restriction being applied",
+                        compiler
+                    ),
+                );
             }
         }
 
-        cmd.push_str(&target_config.cflags);
-        cmd.push(' ');
-        if build_config.compiler == "clang" || build_config.compiler == "gcc" {
-            cmd.push_str("-std=");
-            if build_config.cstandard.is_some() {
-                cmd.push_str(build_config.cstandard.as_ref().unwrap());
-            } else {
-                cmd.push_str("c11");
-            }
-        } else if build_config.compiler == "clang++" || build_config.compiler == "g++" {
-            cmd.push_str("-std=");
-            if build_config.cppstandard.is_some() {
-                cmd.push_str(build_config.cppstandard.as_ref().unwrap());
-            } else {
-                cmd.push_str("c++17");
-            }
-        } else {
-            log(
-                LogLevel::Warn,
-                &format!(
-                    "Unknown compiler: {}, no standard restriction being applied",
-                    build_config.compiler
-                ),
-            );
+        //static libs may end up inside a dll, so they need PIC as well
+        if target_config.typ == "dll" || target_config.typ == "static" {
+            args.push("-fPIC".to_string());
         }
+        args
+    }
 
-        if target_config.typ == "dll" {
-            cmd.push_str(" -fPIC");
+    //builds the source file with its compile command, returns the warnings
+    //or the diagnostics of the compiler if it failed
+    pub fn build(&self) -> Result<Option<String>, String> {
+        let cmd = &self.compile_cmd;
+        if let Some(obj_dir) = Path::new(&self.obj_name).parent() {
+            std::fs::create_dir_all(obj_dir)
+                .map_err(|why| format!("Couldn't create {}: {}", obj_dir.display(), why))?;
         }
 
         log(LogLevel::Info, &format!("Building: {}", &self.name));
-        log(LogLevel::Info, &format!("  Command: {}", &cmd));
-        let output = Command::new("sh")
-            .arg("-c")
-            .arg(&cmd)
-            .output()
-            .expect("failed to execute process");
+        log(LogLevel::Info, &format!("  Command: {}", cmd));
+        let output = cmds::execute(&self.compile_args).map_err(|why| {
+            log(LogLevel::Error, &format!("  Error: {}", &self.name));
+            format!("Could not run {}: {}", cmd, why)
+        })?;
         if output.status.success() {
             log(LogLevel::Info, &format!("  Success: {}", &self.name));
             let stdout = String::from_utf8_lossy(&output.stdout);
-            if stdout.len() > 0 {
+            if !stdout.is_empty() {
                 log(LogLevel::Info, &format!("  Stdout: {}", stdout));
             }
             let stderr = String::from_utf8_lossy(&output.stderr);
-            if stderr.len() > 0 {
-                return Some(stderr.to_string());
+            if !stderr.is_empty() {
+                return Ok(Some(stderr.to_string()));
             }
-            None
+            Ok(None)
         } else {
             log(LogLevel::Error, &format!("  Error: {}", &self.name));
-            log(LogLevel::Error, &format!("  Command: {}", &cmd));
-            log(
-                LogLevel::Error,
-                &format!("  Stdout: {}", String::from_utf8_lossy(&output.stdout)),
-            );
-            log(
-                LogLevel::Error,
-                &format!("  Stderr: {}", String::from_utf8_lossy(&output.stderr)),
-            );
-            std::process::exit(1);
+            log(LogLevel::Error, &format!("  Command: {}", cmd));
+            Err(format!(
+                "{}{}",
+                String::from_utf8_lossy(&output.stdout),
+                String::from_utf8_lossy(&output.stderr)
+            ))
         }
     }
 }
@@ -0,0 +1,376 @@
+//Keeps what every target was last built from in a single database under .bld_cpp,
+//the hashes of its files, the commands of its outputs and the headers of its sources
+use crate::hasher::FileState;
+use crate::utils::log::{log, LogLevel};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// The version of the database layout, bumped when it changes
+/// An older database is migrated, a newer one is dropped, which rebuilds
+/// everything once
+pub const STATE_VERSION: u32 = 2;
+
+/// The path of the build state database
+pub const STATE_PATH: &str = ".bld_cpp/build_state.toml";
+
+//the ending of the hash files older versions kept for every target
+#[cfg(target_os = "windows")]
+const LEGACY_SUFFIX: &str = ".win32.hash";
+#[cfg(not(target_os = "windows"))]
+const LEGACY_SUFFIX: &str = ".linux.hash";
+
+//the database as the build loaded it along with the changes since, written
+//under the lock so targets finishing at the same time do not drop each
+//other's state
+static STATE: Mutex<Option<BuildState>> = Mutex::new(None);
+
+/// The build state of every target, by profile
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct BuildState {
+    pub version: u32,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, ProfileState>,
+    //the hash files of older versions that were imported, removed once the
+    //database is written
+    #[serde(skip)]
+    legacy: Vec<String>,
+}
+
+//the version alone, read first to know the layout of the rest
+#[derive(Deserialize)]
+struct Version {
+    version: u32,
+}
+
+//the layout of version 1, the targets of the packages were kept along with
+//those of the project
+#[derive(Deserialize)]
+struct StateV1 {
+    #[serde(default)]
+    profiles: BTreeMap<String, BTreeMap<String, TargetState>>,
+}
+
+impl StateV1 {
+    //moves every target to the project, a package target among them is
+    //dropped by the next build as a target no longer in the config
+    fn migrate(self) -> BuildState {
+        let profiles = self
+            .profiles
+            .into_iter()
+            .map(|(profile, targets)| {
+                let packages = BTreeMap::new();
+                (profile, ProfileState { targets, packages })
+            })
+            .collect();
+        BuildState {
+            profiles,
+            ..BuildState::empty()
+        }
+    }
+}
+
+/// The build state of the targets built with a profile, kept apart for the
+/// project and every package so targets of the same name do not clash
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct ProfileState {
+    /// The targets of the project, by name
+    #[serde(default)]
+    pub targets: BTreeMap<String, TargetState>,
+    /// The targets of the packages, by package and then by target name
+    #[serde(default)]
+    pub packages: BTreeMap<String, BTreeMap<String, TargetState>>,
+}
+
+impl ProfileState {
+    /// Returns the targets of the project, or of a package
+    /// # Arguments
+    /// * `package` - The package, None for the project
+    pub fn targets(&self, package: Option<&str>) -> Option<&BTreeMap<String, TargetState>> {
+        match package {
+            Some(package) => self.packages.get(package),
+            None => Some(&self.targets),
+        }
+    }
+
+    //returns the targets of the project or of a package, to be changed
+    fn targets_mut(&mut self, package: Option<&str>) -> &mut BTreeMap<String, TargetState> {
+        match package {
+            Some(package) => self.packages.entry(package.to_string()).or_default(),
+            None => &mut self.targets,
+        }
+    }
+}
+
+/// What a target was last built from
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct TargetState {
+    /// The sources, headers and link inputs of the target
+    #[serde(default)]
+    pub files: BTreeMap<String, FileState>,
+    /// The objects and the binary along with the commands that produced them
+    #[serde(default)]
+    pub commands: BTreeMap<String, String>,
+    /// The sources along with the headers they include
+    #[serde(default)]
+    pub deps: BTreeMap<String, Vec<String>>,
+}
+
+impl TargetState {
+    /// Returns the recorded file states
+    pub fn files(&self) -> HashMap<String, FileState> {
+        self.files.clone().into_iter().collect()
+    }
+
+    /// Returns the recorded commands
+    pub fn commands(&self) -> HashMap<String, String> {
+        self.commands.clone().into_iter().collect()
+    }
+
+    /// Returns the recorded headers of every source
+    pub fn deps(&self) -> HashMap<String, Vec<String>> {
+        self.deps.clone().into_iter().collect()
+    }
+}
+
+impl BuildState {
+    /// Loads the build state database, which is kept for the targets to store
+    /// their state in
+    /// An older database is migrated and the hash files of older versions are
+    /// imported into the profile. A corrupt or newer database is replaced by
+    /// an empty one, which rebuilds everything once
+    /// # Arguments
+    /// * `profile` - The profile being built
+    pub fn load(profile: &str) -> Self {
+        let mut state = Self::read().unwrap_or_else(|why| {
+            log(
+                LogLevel::Warn,
+                &format!("Build state {} {}, rebuilding", STATE_PATH, why),
+            );
+            Self::empty()
+        });
+        state.import_legacy(profile);
+        *STATE.lock().unwrap() = Some(state.clone());
+        state
+    }
+
+    //reads the database, returning why it can not be used if it is not usable
+    //a missing database is an empty one
+    fn read() -> Result<Self, String> {
+        let contents = match fs::read_to_string(STATE_PATH) {
+            Ok(contents) => contents,
+            Err(_) => return Ok(Self::empty()),
+        };
+        let corrupt = |e: toml::de::Error| format!("is corrupt: {}", e.message());
+        match toml::from_str::<Version>(&contents)
+            .map_err(corrupt)?
+            .version
+        {
+            1 => Ok(toml::from_str::<StateV1>(&contents)
+                .map_err(corrupt)?
+                .migrate()),
+            STATE_VERSION => toml::from_str::<BuildState>(&contents).map_err(corrupt),
+            version => Err(format!(
+                "has version {} but only {} is known",
+                version, STATE_VERSION
+            )),
+        }
+    }
+
+    //returns a database holding no targets
+    fn empty() -> Self {
+        BuildState {
+            version: STATE_VERSION,
+            profiles: BTreeMap::new(),
+            legacy: Vec::new(),
+        }
+    }
+
+    //imports the hash files older versions kept for the targets of the project
+    //their hashes are SHA-1 and no commands were kept with them, so the files
+    //are recorded without a hash and the targets are built again once, but a
+    //target that is no longer in the config is still known and cleaned up
+    fn import_legacy(&mut self, profile: &str) {
+        let Ok(entries) = fs::read_dir(".bld_cpp") else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let Some(name) = file_name.strip_suffix(LEGACY_SUFFIX) else {
+                continue;
+            };
+            let path = legacy_path(name);
+            let contents = fs::read_to_string(&path).unwrap_or_default();
+            //every line is a path followed by its hash, paths may hold spaces
+            let files = contents
+                .lines()
+                .filter_map(|line| line.rsplit_once(' '))
+                .map(|(file, _)| {
+                    let unhashed = FileState {
+                        hash: String::new(),
+                        mtime: 0,
+                        size: 0,
+                    };
+                    (file.to_string(), unhashed)
+                })
+                .collect();
+            self.profiles
+                .entry(profile.to_string())
+                .or_default()
+                .targets
+                .entry(name.to_string())
+                .or_insert_with(|| TargetState {
+                    files,
+                    ..TargetState::default()
+                });
+            self.legacy.push(path);
+        }
+    }
+
+    /// Writes the database to a temporary file and renames it over the old one,
+    /// so an interrupted build never leaves a partly written database
+    /// The hash files of older versions imported into it are removed after
+    pub fn save(&mut self) {
+        let contents = toml::to_string(self).unwrap_or_else(|why| {
+            log(
+                LogLevel::Error,
+                &format!("Failed to serialize build state: {}", why),
+            );
+            std::process::exit(1);
+        });
+        if let Some(dir) = Path::new(STATE_PATH).parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        let tmp_path = format!("{}.tmp", STATE_PATH);
+        fs::write(&tmp_path, contents)
+            .and_then(|_| fs::rename(&tmp_path, STATE_PATH))
+            .unwrap_or_else(|why| {
+                log(
+                    LogLevel::Error,
+                    &format!("Failed to write build state {}: {}", STATE_PATH, why),
+                );
+                std::process::exit(1);
+            });
+        for path in self.legacy.drain(..) {
+            let _ = fs::remove_file(&path);
+            log(LogLevel::Info, &format!("Cleaning: {}", &path));
+        }
+    }
+
+    /// Returns the state of a target, which is empty if it was never built
+    /// # Arguments
+    /// * `package` - The package of the target, None for the targets of the project
+    /// * `name` - Name of the target
+    /// * `profile` - The profile being built
+    pub fn target(&self, package: Option<&str>, name: &str, profile: &str) -> TargetState {
+        self.profiles
+            .get(profile)
+            .and_then(|profile| profile.targets(package))
+            .and_then(|targets| targets.get(name))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Stores the state of a target in the database
+    /// # Arguments
+    /// * `package` - The package of the target, None for the targets of the project
+    /// * `name` - Name of the target
+    /// * `profile` - The profile being built
+    /// * `target_state` - What the target was built from
+    pub fn save_target(
+        package: Option<&str>,
+        name: &str,
+        profile: &str,
+        target_state: TargetState,
+    ) {
+        Self::update(|state| {
+            state
+                .profiles
+                .entry(profile.to_string())
+                .or_default()
+                .targets_mut(package)
+                .insert(name.to_string(), target_state);
+            true
+        });
+    }
+
+    /// Forgets the state of a target, so its next build starts over
+    /// The hash file older versions kept for the target is removed
+    /// # Arguments
+    /// * `package` - The package of the target, None for the targets of the project
+    /// * `name` - Name of the target
+    /// * `profile` - The profile being cleaned
+    pub fn remove_target(package: Option<&str>, name: &str, profile: &str) {
+        let removed = Self::update(|state| {
+            state
+                .profiles
+                .get_mut(profile)
+                .is_some_and(|profile_state| {
+                    let removed = profile_state.targets_mut(package).remove(name).is_some();
+                    profile_state
+                        .packages
+                        .retain(|_, targets| !targets.is_empty());
+                    removed
+                })
+        });
+        if removed {
+            log(
+                LogLevel::Info,
+                &format!("Cleaning: build state of {}", name),
+            );
+        }
+        remove_legacy(name);
+    }
+
+    //changes the database held since the build loaded it, which is read first
+    //if it was not loaded, like when cleaning, and writes it if it changed
+    //returns whether it changed
+    fn update(change: impl FnOnce(&mut BuildState) -> bool) -> bool {
+        let mut held = STATE.lock().unwrap();
+        //an unusable database was warned about when the build loaded it
+        let state = held.get_or_insert_with(|| Self::read().unwrap_or_else(|_| Self::empty()));
+        let changed = change(state);
+        if changed {
+            state.save();
+        }
+        changed
+    }
+}
+
+//returns the path of the file older versions kept the hashes of the files of
+//a target in
+fn legacy_path(name: &str) -> String {
+    format!(".bld_cpp/{}{}", name, LEGACY_SUFFIX)
+}
+
+//removes the hash file older versions kept for a target
+fn remove_legacy(name: &str) {
+    let path = legacy_path(name);
+    if Path::new(&path).exists() {
+        let _ = fs::remove_file(&path);
+        log(LogLevel::Info, &format!("Cleaning: {}", &path));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrates_version_1_targets_to_the_project() {
+        let v1 = r#"
+version = 1
+
+[profiles.debug.app.commands]
+"bin/app" = "cc -o bin/app a.o"
+"#;
+        assert_eq!(toml::from_str::<Version>(v1).unwrap().version, 1);
+        let state = toml::from_str::<StateV1>(v1).unwrap().migrate();
+        assert_eq!(state.version, STATE_VERSION);
+        let app = state.target(None, "app", "debug");
+        assert_eq!(app.commands["bin/app"], "cc -o bin/app a.o");
+        assert!(state.profiles["debug"].packages.is_empty());
+    }
+}
@@ -1,65 +1,83 @@
+use super::cmds;
+use super::pkg_config;
+use super::sched::BuildOptions;
 use super::src::Src;
-use crate::hasher;
+use super::state::{BuildState, TargetState};
+use super::timings;
+use crate::hasher::{self, FileState};
 use crate::utils::{
-    configs::{BuildConfig, TargetConfig},
+    configs::{BuildConfig, Define, TargetConfig},
     log::{log, LogLevel},
     package::Package,
 };
-use colored::Colorize;
-use indicatif::{ProgressBar, ProgressStyle};
 use itertools::Itertools;
 use rayon::prelude::*;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::io::{Read, Write};
-use std::path::{Path, PathBuf};
-use std::process::Command;
-use std::sync::{Arc, Mutex};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, Instant};
 
 //Represents a target
+#[derive(Clone)]
 pub struct Target<'a> {
     pub srcs: Vec<Src>,
     pub build_config: &'a BuildConfig,
     pub target_config: &'a TargetConfig,
     pub dependant_includes: HashMap<String, Vec<String>>,
     pub bin_path: String,
-    pub hash_file_path: String,
-    pub path_hash: HashMap<String, String>,
+    pub recorded_cmds: HashMap<String, String>,
+    pub path_hash: HashMap<String, FileState>,
     pub dependant_libs: Vec<Target<'a>>,
     pub packages: &'a Vec<Package>,
+    pub include_dirs: Vec<String>,
+    pub check_time: Duration,
 }
 
 impl<'a> Target<'a> {
-    pub fn obj_dir() -> &'static str {
+    /// Returns the directory holding all outputs of a profile
+    pub fn profile_dir(profile: &str) -> String {
+        format!(".bld_cpp/{}", profile)
+    }
+
+    pub fn obj_dir(profile: &str) -> String {
         #[cfg(target_os = "windows")]
-        return ".bld_cpp/obj_win32";
+        return format!("{}/obj_win32", Self::profile_dir(profile));
         #[cfg(target_os = "linux")]
-        return ".bld_cpp/obj_linux";
+        return format!("{}/obj_linux", Self::profile_dir(profile));
         #[cfg(target_os = "android")]
-        return ".bld_cpp/obj_android";
+        return format!("{}/obj_android", Self::profile_dir(profile));
     }
 
-    pub fn build_dir() -> &'static str {
-        ".bld_cpp/bin"
+    /// Returns the dir the objects of the targets of packages are kept in,
+    /// apart from those of the project
+    pub fn package_obj_dir(profile: &str) -> String {
+        format!("{}_packages", Self::obj_dir(profile))
     }
 
-    /// Creates a new target
+    /// Returns the dir the objects of a target are kept in
     /// # Arguments
-    /// * `build_config` - Build config
-    /// * `target_config` - Target config
-    /// * `targets` - All targets
-    /// * `packages` - All packages
-    pub fn new(
-        build_config: &'a BuildConfig,
-        target_config: &'a TargetConfig,
-        targets: &'a Vec<TargetConfig>,
-        packages: &'a Vec<Package>,
-    ) -> Self {
-        let srcs = Vec::new();
-        let dependant_includes: HashMap<String, Vec<String>> = HashMap::new();
+    /// * `profile` - The profile being built
+    /// * `package` - The package of the target, None for the targets of the project
+    /// * `name` - Name of the target
+    pub fn target_obj_dir(profile: &str, package: Option<&str>, name: &str) -> String {
+        match package {
+            Some(package) => format!("{}/{}/{}", Self::package_obj_dir(profile), package, name),
+            None => format!("{}/{}", Self::obj_dir(profile), name),
+        }
+    }
+
+    pub fn build_dir(profile: &str) -> String {
+        format!("{}/bin", Self::profile_dir(profile))
+    }
 
+    /// Returns the path of the binary produced by a target
+    /// # Arguments
+    /// * `target_config` - Target config
+    /// * `profile` - The profile being built
+    pub fn bin_path_for(target_config: &TargetConfig, profile: &str) -> String {
         let mut bin_path = String::new();
-        bin_path.push_str(Self::build_dir());
+        bin_path.push_str(&Self::build_dir(profile));
         bin_path.push('/');
         bin_path.push_str(&target_config.name);
         #[cfg(target_os = "windows")]
@@ -67,42 +85,87 @@ impl<'a> Target<'a> {
             bin_path.push_str(".exe");
         } else if target_config.typ == "dll" {
             bin_path.push_str(".dll");
+        } else if target_config.typ == "static" {
+            bin_path.push_str(".a");
         }
         #[cfg(target_os = "linux")]
         if target_config.typ == "exe" {
             bin_path.push_str("");
         } else if target_config.typ == "dll" {
             bin_path.push_str(".so");
+        } else if target_config.typ == "static" {
+            bin_path.push_str(".a");
         }
         #[cfg(target_os = "android")]
         if target_config.typ == "exe" {
             bin_path.push_str("");
         } else if target_config.typ == "dll" {
             bin_path.push_str(".so");
+        } else if target_config.typ == "static" {
+            bin_path.push_str(".a");
         }
+        bin_path
+    }
 
-        #[cfg(target_os = "windows")]
-        let hash_file_path = format!(".bld_cpp/{}.win32.hash", &target_config.name);
-        #[cfg(target_os = "linux")]
-        let hash_file_path = format!(".bld_cpp/{}.linux.hash", &target_config.name);
-        #[cfg(target_os = "android")]
-        let hash_file_path = format!(".bld_cpp/{}.linux.hash", &target_config.name);
+    /// Creates a new target
+    /// # Arguments
+    /// * `build_config` - Build config
+    /// * `target_config` - Target config
+    /// * `targets` - All targets
+    /// * `packages` - All packages
+    /// * `state` - The build state loaded at the start of the build
+    /// * `created` - Targets created before, deps found here are copied instead of created again
+    pub fn new(
+        build_config: &'a BuildConfig,
+        target_config: &'a TargetConfig,
+        targets: &'a Vec<TargetConfig>,
+        packages: &'a Vec<Package>,
+        state: &BuildState,
+        created: &[Target<'a>],
+    ) -> Self {
+        let srcs = Vec::new();
+        let dependant_includes: HashMap<String, Vec<String>> = HashMap::new();
 
-        let path_hash = hasher::load_hashes_from_file(&hash_file_path);
+        let bin_path = Self::bin_path_for(target_config, &build_config.profile);
+        let target_state = state.target(
+            target_config.package.as_deref(),
+            &target_config.name,
+            &build_config.profile,
+        );
+        let recorded_cmds = target_state.commands();
+
+        let path_hash = target_state.files();
         let mut dependant_libs = Vec::new();
         for dependant_lib in &target_config.deps {
+            if let Some(dep) = created
+                .iter()
+                .find(|dep| dep.target_config.name == *dependant_lib)
+            {
+                dependant_libs.push(dep.clone());
+                continue;
+            }
             for target in targets {
                 if target.name == *dependant_lib {
-                    dependant_libs.push(Target::new(build_config, target, targets, packages));
+                    dependant_libs.push(Target::new(
+                        build_config,
+                        target,
+                        targets,
+                        packages,
+                        state,
+                        created,
+                    ));
                 }
             }
         }
         for dep_lib in &dependant_libs {
-            if dep_lib.target_config.typ != "dll" {
-                log(LogLevel::Error, "Can add only dlls as dependant libs");
+            if dep_lib.target_config.typ == "exe" {
+                log(
+                    LogLevel::Error,
+                    "Can add only dlls, static libs or interfaces as dependant libs",
+                );
                 log(
                     LogLevel::Error,
-                    &format!("Target: {} is not a dll", dep_lib.target_config.name),
+                    &format!("Target: {} is not a library", dep_lib.target_config.name),
                 );
                 log(
                     LogLevel::Error,
@@ -118,7 +181,8 @@ impl<'a> Target<'a> {
                     &format!("Adding dependant lib: {}", dep_lib.target_config.name),
                 );
             }
-            if !dep_lib.target_config.name.starts_with("lib") {
+            if dep_lib.target_config.typ == "dll" && !dep_lib.target_config.name.starts_with("lib")
+            {
                 log(LogLevel::Error, "Dependant lib name must start with lib");
                 log(
                     LogLevel::Error,
@@ -130,34 +194,6 @@ impl<'a> Target<'a> {
                 std::process::exit(1);
             }
         }
-        if target_config.deps.len() > dependant_libs.len() + packages.len() {
-            log(LogLevel::Error, "Dependant libs not found");
-            log(
-                LogLevel::Error,
-                &format!("Dependant libs: {:?}", target_config.deps),
-            );
-            log(
-                LogLevel::Error,
-                &format!(
-                    "Found libs: {:?}",
-                    targets
-                        .iter()
-                        .map(|x| {
-                            if x.typ == "dll" {
-                                x.name.clone()
-                            } else {
-                                "".to_string()
-                            }
-                        })
-                        .collect::<Vec<String>>()
-                        .into_iter()
-                        .filter(|x| !x.is_empty())
-                        .collect::<Vec<String>>()
-                ),
-            );
-            std::process::exit(1);
-        }
-
         let mut target = Target::<'a> {
             srcs,
             build_config,
@@ -165,67 +201,100 @@ impl<'a> Target<'a> {
             dependant_includes,
             bin_path,
             path_hash,
-            hash_file_path,
+            recorded_cmds,
             dependant_libs,
             packages,
+            include_dirs: Vec::new(),
+            check_time: Duration::ZERO,
         };
-        target.get_srcs(&target_config.src, target_config);
+        target.include_dirs = target.collect_include_dirs();
+        //a missing pkg-config module stops the build before anything compiles
+        target.pkg_config();
+        //interface targets are header only
+        if target_config.typ != "interface" {
+            let recorded_deps = target_state.deps();
+            target.get_srcs(target_config, &recorded_deps);
+        }
         target
     }
 
-    /// Builds the target
+    /// Checks which sources of the target have to be compiled and logs why
+    /// Returns whether each source has to be compiled, in the order of srcs
     /// # Arguments
     /// * `gen_cc` - Generate compile_commands.json
-    pub fn build(&mut self, gen_cc: bool) {
-        if !Path::new(".bld_cpp").exists() {
-            std::fs::create_dir(".bld_cpp").unwrap_or_else(|why| {
+    /// * `options` - Whether to explain why each source is compiled, and
+    ///   whether this is a dry run that must not remove stale objects
+    pub fn plan(&mut self, gen_cc: bool, options: &BuildOptions) -> Vec<bool> {
+        let profile_dir = Self::profile_dir(&self.build_config.profile);
+        //a dry run leaves the file system as it is
+        if !options.dry_run && !Path::new(&profile_dir).exists() {
+            std::fs::create_dir_all(&profile_dir).unwrap_or_else(|why| {
                 log(
                     LogLevel::Error,
-                    &format!("Couldn't create .bld_cpp directory: {}", why),
+                    &format!("Couldn't create {} directory: {}", profile_dir, why),
                 );
                 std::process::exit(1);
             });
         }
-        for pkg in self.packages {
-            for target in &pkg.target_configs {
-                let empty: Vec<Package> = Vec::new();
-                if target.typ == "dll" {
-                    let mut pkg_tgt =
-                        Target::new(&pkg.build_config, target, &pkg.target_configs, &empty);
-                    pkg_tgt.build(gen_cc);
-                }
-            }
+        if self.target_config.typ == "interface" {
+            log(
+                LogLevel::Info,
+                &format!(
+                    "Target: {} is an interface, nothing to build",
+                    &self.target_config.name
+                ),
+            );
+            return Vec::new();
+        }
+        if !options.dry_run {
+            self.remove_stale_objects();
         }
-        let mut to_link: bool = false;
-        let mut link_causer: Vec<&str> = Vec::new();
         let mut srcs_needed = 0;
         let total_srcs = self.srcs.len();
-        let mut src_ccs = Vec::new();
-        for src in &self.srcs {
-            let (to_build, _) = src.to_build(&self.path_hash);
+        let mut cmd_changes: Vec<String> = Vec::new();
+        let check_start = Instant::now();
+        //every source is checked once, the files they share are hashed once
+        let checks = self
+            .srcs
+            .par_iter()
+            .map(|src| src.to_build(&self.path_hash, &self.recorded_cmds))
+            .collect::<Vec<(bool, String)>>();
+        let srcs_to_build = checks
+            .iter()
+            .map(|(to_build, _)| *to_build)
+            .collect::<Vec<bool>>();
+        for (src, &to_build) in self.srcs.iter().zip(&srcs_to_build) {
             log(LogLevel::Debug, &format!("{}: {}", src.path, to_build));
             if to_build {
-                to_link = true;
-                link_causer.push(&src.path);
                 srcs_needed += 1;
             }
-            if gen_cc {
-                src_ccs.push(self.gen_cc(src));
+            if let Some(change) = src.command_change(&self.recorded_cmds) {
+                cmd_changes.push(change);
             }
         }
+        self.check_time = check_start.elapsed();
         if gen_cc {
+            let gen_start = Instant::now();
             let mut file = std::fs::OpenOptions::new()
-                .write(true)
                 .append(true)
                 .open("./compile_commands.json")
                 .unwrap();
-            for src_cc in src_ccs {
-                if let Err(e) = writeln!(file, "{},", src_cc) {
+            //the entries are separated by commas, the file starts out as just [
+            let mut first = file.metadata().map_or(true, |metadata| metadata.len() <= 1);
+            for src in &self.srcs {
+                let separator = if first { "" } else { ",\n" };
+                if let Err(e) = write!(file, "{}{}", separator, self.gen_cc(src)) {
                     eprintln!("Couldn't write to file: {}", e);
                 }
+                first = false;
             }
+            timings::record_since(
+                "generate",
+                &format!("compile_commands.json ({})", self.target_config.name),
+                gen_start,
+            );
         }
-        if to_link {
+        if srcs_needed > 0 {
             log(
                 LogLevel::Log,
                 &format!("Compiling Target: {}", &self.target_config.name),
@@ -237,387 +306,747 @@ impl<'a> Target<'a> {
                     srcs_needed, total_srcs
                 ),
             );
-            if !Path::new(Self::obj_dir()).exists() {
-                fs::create_dir(Self::obj_dir()).unwrap_or_else(|why| {
+            for change in cmd_changes.iter().unique() {
+                log(LogLevel::Log, &format!("\t Recompiling since {}", change));
+            }
+            if options.explain {
+                for (_, reason) in checks.iter().filter(|(to_build, _)| *to_build) {
+                    log(LogLevel::Log, &format!("\t {}", reason.trim()));
+                }
+            }
+            let obj_dir = Self::obj_dir(&self.build_config.profile);
+            if !options.dry_run && !Path::new(&obj_dir).exists() {
+                fs::create_dir_all(&obj_dir).unwrap_or_else(|why| {
                     log(
                         LogLevel::Error,
                         &format!("Couldn't create obj dir: {}", why),
                     );
                 });
             }
-        } else {
-            log(
-                LogLevel::Log,
-                &format!("Target: {} is up to date", &self.target_config.name),
-            );
-            return;
         }
-        let progress_bar = Arc::new(Mutex::new(ProgressBar::new(srcs_needed as u64)));
+        srcs_to_build
+    }
 
-        let num_complete = Arc::new(Mutex::new(0));
-        let src_hash_to_update = Arc::new(Mutex::new(Vec::new()));
-        let warns = Arc::new(Mutex::new(Vec::new()));
-        self.srcs.par_iter().for_each(|src| {
-            let (to_build, _message) = src.to_build(&self.path_hash);
-            log(LogLevel::Debug, &format!("{}: {}", src.path, to_build));
-            if to_build {
-                let warn = src.build(self.build_config, self.target_config, &self.dependant_libs);
-                if let Some(warn) = warn {
-                    warns.lock().unwrap().push(warn);
-                }
-                src_hash_to_update.lock().unwrap().push(src);
-                log(LogLevel::Info, &format!("Compiled: {}", src.path));
-                let log_level = std::env::var("BUILDER_CPP_LOG_LEVEL").unwrap_or("".to_string());
-                if !(log_level == "Info" || log_level == "Debug") {
-                    let mut num_complete = num_complete.lock().unwrap();
-                    *num_complete += 1;
-                    let progress_bar = progress_bar.lock().unwrap();
-                    let template = format!(
-                        "    {}{}",
-                        "Compiling :".cyan(),
-                        "[{bar:40.}] {pos}/{len} ({percent}%) {msg}[{elapsed_precise}] "
-                    );
-                    progress_bar.set_style(
-                        ProgressStyle::with_template(&template)
-                            .unwrap()
-                            .progress_chars("=>-"),
-                    );
-                    progress_bar.inc(1);
-                }
-            }
-        });
-        let warns = warns.lock().unwrap();
-        if warns.len() > 0 {
+    /// Links the target once its sources are compiled and its deps are linked
+    /// A target none of whose sources were compiled is only linked if its
+    /// link command or one of its link inputs changed
+    /// Returns the diagnostics of the linker if linking failed
+    /// # Arguments
+    /// * `compiled` - The sources that were compiled, along with the headers
+    ///   the compiler found for them
+    /// * `warns` - The warnings emitted while compiling them
+    pub fn finish(
+        &mut self,
+        compiled: Vec<(String, Option<Vec<String>>)>,
+        warns: Vec<String>,
+    ) -> Result<(), String> {
+        if !warns.is_empty() {
             log(LogLevel::Warn, "Warnings emitted during build:");
             for warn in warns.iter() {
                 log(LogLevel::Warn, &format!("\t{}", warn));
             }
         }
+        let link_args = self.link_command(&self.dependant_libs);
+        let link_cmd = cmds::join_args(&link_args);
+        if compiled.is_empty() {
+            if let Some(reason) = self.relink_reason(&link_cmd) {
+                log(
+                    LogLevel::Log,
+                    &format!("Linking Target: {}", &self.target_config.name),
+                );
+                log(LogLevel::Log, &format!("\t Linking since {}", reason));
+                self.save_link_input_hashes()?;
+                return self.link_and_save(&link_args, link_cmd);
+            } else {
+                log(
+                    LogLevel::Log,
+                    &format!(
+                        "Target: {} is up to date (checked in {:.2?})",
+                        &self.target_config.name, self.check_time
+                    ),
+                );
+                let pruned = self.prune_file_states();
+                if self.refresh_file_states()? || pruned {
+                    self.save_state(Some(link_cmd));
+                }
+            }
+            return Ok(());
+        }
+
+        self.save_compiled_hashes(compiled)?;
+        log(
+            LogLevel::Log,
+            &format!(
+                "Linking: {} since source files were compiled",
+                &self.target_config.name
+            ),
+        );
+        self.save_link_input_hashes()?;
+        self.link_and_save(&link_args, link_cmd)
+    }
+
+    /// Records the state of the sources that were compiled when the target is
+    /// not linked since another job failed, so the next build does not compile
+    /// them again. The sources that failed or did not get to compile lose their
+    /// hash, so they are compiled in the next build even though the headers
+    /// they share with the compiled sources are recorded as unchanged
+    /// # Arguments
+    /// * `compiled` - The sources that were compiled, along with the headers
+    ///   the compiler found for them
+    /// * `pending` - The sources that still have to be compiled
+    pub fn save_compiled(
+        &mut self,
+        compiled: Vec<(String, Option<Vec<String>>)>,
+        pending: &[String],
+    ) -> Result<(), String> {
+        self.save_compiled_hashes(compiled)?;
+        for path in pending {
+            self.path_hash.remove(path);
+        }
+        //the binary is still the one linked with the recorded command
+        let link_cmd = self.recorded_cmds.get(&self.bin_path).cloned();
+        self.save_state(link_cmd);
+        Ok(())
+    }
 
-        for src in src_hash_to_update.lock().unwrap().iter() {
-            hasher::save_hash(&src.path, &mut self.path_hash);
+    //records the hashes of the compiled sources and of the headers of the
+    //sources, along with the headers the compiler found for them
+    fn save_compiled_hashes(
+        &mut self,
+        compiled: Vec<(String, Option<Vec<String>>)>,
+    ) -> Result<(), String> {
+        for (path, includes) in compiled {
+            hasher::save_hash(&path, &mut self.path_hash)?;
+            log(LogLevel::Info, &format!("\tFile: {}", &path));
+            //the compiler knows every header the source includes
+            if let (Some(src), Some(includes)) =
+                (self.srcs.iter_mut().find(|src| src.path == path), includes)
+            {
+                src.dependant_includes = includes;
+            }
         }
-        if to_link {
-            log(LogLevel::Log, "Linking: Since source files were compiled");
-            for src in link_causer {
-                log(LogLevel::Info, &format!("\tFile: {}", &src));
+        for src in &self.srcs {
+            for include in &src.dependant_includes {
+                hasher::save_hash(include, &mut self.path_hash)?;
             }
-            for src in &self.srcs {
-                for include in &src.dependant_includes {
-                    hasher::save_hash(include, &mut self.path_hash);
+        }
+        Ok(())
+    }
+
+    /// Prints the command that would link the target without running it
+    /// Returns true if the target would be linked
+    /// # Arguments
+    /// * `compiled` - Whether any of its sources would be compiled
+    /// * `linked` - The binaries the dry run would link before this target
+    pub fn dry_run_link(&self, compiled: bool, linked: &[String]) -> bool {
+        let link_args = self.link_command(&self.dependant_libs);
+        let reason = if compiled {
+            Some("source files would be compiled".to_string())
+        } else if let Some(input) = self
+            .link_inputs()
+            .into_iter()
+            .find(|input| linked.contains(input))
+        {
+            Some(format!("link input would change: {}", input))
+        } else {
+            self.relink_reason(&cmds::join_args(&link_args))
+        };
+        match reason {
+            Some(reason) => {
+                log(
+                    LogLevel::Log,
+                    &format!(
+                        "Would link target: {} since {}",
+                        &self.target_config.name, reason
+                    ),
+                );
+                log(LogLevel::Log, &cmds::join_args(&link_args));
+                true
+            }
+            None => {
+                log(
+                    LogLevel::Log,
+                    &format!("Target: {} is up to date", &self.target_config.name),
+                );
+                false
+            }
+        }
+    }
+
+    //links the target and records its state, the link command is only
+    //recorded if linking succeeded so the next build links it again
+    fn link_and_save(&mut self, link_args: &[String], link_cmd: String) -> Result<(), String> {
+        let linked = self.link(link_args);
+        self.save_state(linked.is_ok().then_some(link_cmd));
+        linked
+    }
+
+    //returns why the target has to be linked again even though none of its
+    //sources were compiled, None if the binary is up to date
+    fn relink_reason(&self, link_cmd: &str) -> Option<String> {
+        if !Path::new(&self.bin_path).exists() {
+            return Some(format!("binary does not exist: {}", &self.bin_path));
+        }
+        match self.recorded_cmds.get(&self.bin_path) {
+            None => return Some("no link command was recorded".to_string()),
+            Some(recorded_cmd) if recorded_cmd != link_cmd => {
+                //a removed source or dep only drops its input from the command
+                let args = cmds::split_args(link_cmd);
+                let removed = cmds::split_args(recorded_cmd).into_iter().find(|arg| {
+                    !args.contains(arg)
+                        && *arg != self.bin_path
+                        && (self.recorded_cmds.contains_key(arg)
+                            || self.path_hash.contains_key(arg))
+                });
+                return Some(match removed {
+                    Some(input) => format!("link input removed: {}", input),
+                    None => cmds::describe_change(recorded_cmd, link_cmd),
+                });
+            }
+            _ => {}
+        }
+        self.link_inputs()
+            .into_iter()
+            .find(|input| hasher::is_file_changed(input, &self.path_hash))
+            .map(|input| format!("link input changed: {}", input))
+    }
+
+    //returns the files the target is linked from, its objects followed by the
+    //libraries of its deps and packages
+    fn link_inputs(&self) -> Vec<String> {
+        //a dll is linked by name, the libraries it links itself are not inputs
+        fn push_dep_bins(inputs: &mut Vec<String>, dep_target: &Target) {
+            if dep_target.target_config.typ != "interface" {
+                inputs.push(dep_target.bin_path.clone());
+            }
+            if dep_target.target_config.typ != "dll" {
+                for dep in &dep_target.dependant_libs {
+                    push_dep_bins(inputs, dep);
+                }
+            }
+        }
+
+        let mut inputs = self
+            .srcs
+            .iter()
+            .map(|src| src.obj_name.clone())
+            .collect::<Vec<String>>();
+        //the archiver only takes the objects
+        if self.target_config.typ == "static" {
+            return inputs;
+        }
+        for dep_target in &self.dependant_libs {
+            push_dep_bins(&mut inputs, dep_target);
+        }
+        for package in self.packages {
+            for target in &package.target_configs {
+                if target.typ != "interface" {
+                    inputs.push(Self::bin_path_for(target, &self.build_config.profile));
                 }
             }
-            hasher::save_hashes_to_file(&self.hash_file_path, &self.path_hash);
-            self.link(&self.dependant_libs);
         }
+        inputs.into_iter().unique().collect()
+    }
+
+    //records the hashes of the link inputs, the hash file is written once
+    //linking succeeds
+    fn save_link_input_hashes(&mut self) -> Result<(), String> {
+        for input in self.link_inputs() {
+            hasher::save_hash(&input, &mut self.path_hash)?;
+        }
+        Ok(())
+    }
+
+    //records the new times of the files that were touched without changing,
+    //so they are not hashed again in the next build
+    //returns true if any file was touched
+    fn refresh_file_states(&mut self) -> Result<bool, String> {
+        let recorded = self.path_hash.clone();
+        for path in recorded.keys() {
+            hasher::save_hash(path, &mut self.path_hash)?;
+        }
+        Ok(recorded != self.path_hash)
+    }
+
+    //forgets the files that are no longer sources, headers of sources or
+    //link inputs of the target
+    //returns true if any file was forgotten
+    fn prune_file_states(&mut self) -> bool {
+        let mut used = self.link_inputs().into_iter().collect::<HashSet<String>>();
+        for src in &self.srcs {
+            used.insert(src.path.clone());
+            used.extend(src.dependant_includes.iter().cloned());
+        }
+        let recorded = self.path_hash.len();
+        self.path_hash.retain(|path, _| used.contains(path));
+        recorded != self.path_hash.len()
+    }
+
+    //returns the dir the objects of the target are kept in
+    fn obj_dir_of_target(&self) -> String {
+        Self::target_obj_dir(
+            &self.build_config.profile,
+            self.target_config.package.as_deref(),
+            &self.target_config.name,
+        )
     }
 
-    /// Links the target
+    //removes the objects and dependency files in the obj dir of the target
+    //that belong to none of its sources, like those of deleted or renamed sources
+    fn remove_stale_objects(&self) {
+        let obj_dir = self.obj_dir_of_target();
+        let mut outputs = HashSet::new();
+        for src in &self.srcs {
+            outputs.insert(PathBuf::from(&src.obj_name));
+            outputs.insert(PathBuf::from(src.depfile_path()));
+        }
+        remove_files_except(Path::new(&obj_dir), &outputs);
+    }
+
+    /// Removes the outputs and the build state of the targets that were built
+    /// before but are no longer in the config
     /// # Arguments
-    /// * `dep_targets` - The targets that this target depends on
-    pub fn link(&self, dep_targets: &Vec<Target>) {
-        let mut objs = Vec::new();
-        if !Path::new(Self::build_dir()).exists() {
-            let cmd = format!("mkdir -p {}", Self::build_dir());
-            let output = Command::new("sh")
-                .arg("-c")
-                .arg(cmd)
-                .output()
-                .expect("failed to execute process");
-            if !output.status.success() {
+    /// * `state` - The build state loaded at the start of the build
+    /// * `profile` - The profile being built
+    /// * `live` - The targets of the project and of its packages
+    pub fn remove_stale_targets(state: &BuildState, profile: &str, live: &[Target]) {
+        let Some(profile_state) = state.profiles.get(profile) else {
+            return;
+        };
+        let scopes = std::iter::once((None, &profile_state.targets)).chain(
+            profile_state
+                .packages
+                .iter()
+                .map(|(package, targets)| (Some(package.as_str()), targets)),
+        );
+        for (package, targets) in scopes {
+            for (name, target_state) in targets {
+                let is_live = live.iter().any(|target| {
+                    target.target_config.package.as_deref() == package
+                        && target.target_config.name == *name
+                });
+                if is_live {
+                    continue;
+                }
                 log(
-                    LogLevel::Error,
+                    LogLevel::Log,
                     &format!(
-                        "Couldn't create build dir: {}",
-                        String::from_utf8_lossy(&output.stderr)
+                        "Removing target: {} since it is no longer in the config",
+                        name
                     ),
                 );
+                //the outputs are the objects and the binary the commands were
+                //recorded for, a target of the same name in the project or in
+                //a package may still build the same binary
+                for output in target_state.commands.keys() {
+                    let shared = live.iter().any(|target| target.bin_path == *output);
+                    if !shared && Path::new(output).exists() {
+                        let _ = fs::remove_file(output);
+                        log(LogLevel::Info, &format!("Cleaning: {}", output));
+                    }
+                }
+                let obj_dir = Self::target_obj_dir(profile, package, name);
+                if Path::new(&obj_dir).exists() {
+                    let _ = fs::remove_dir_all(&obj_dir);
+                    log(LogLevel::Info, &format!("Cleaning: {}", obj_dir));
+                }
+                BuildState::remove_target(package, name, profile);
             }
         }
+    }
+
+    //records the file hashes, the commands the sources were compiled with and
+    //the target was linked with and the headers of the sources in the build
+    //state, so the next build knows what changed
+    fn save_state(&mut self, link_cmd: Option<String>) {
+        let mut recorded_cmds = HashMap::new();
+        for src in &self.srcs {
+            recorded_cmds.insert(src.obj_name.clone(), src.compile_cmd.clone());
+        }
+        if let Some(link_cmd) = link_cmd {
+            recorded_cmds.insert(self.bin_path.clone(), link_cmd);
+        }
+        self.prune_file_states();
+        let target_state = TargetState {
+            files: self.path_hash.clone().into_iter().collect(),
+            commands: recorded_cmds.clone().into_iter().collect(),
+            deps: self
+                .srcs
+                .iter()
+                .map(|src| (src.path.clone(), src.dependant_includes.clone()))
+                .collect(),
+        };
+        BuildState::save_target(
+            self.target_config.package.as_deref(),
+            &self.target_config.name,
+            &self.build_config.profile,
+            target_state,
+        );
+        self.recorded_cmds = recorded_cmds;
+    }
+
+    /// Returns the arguments that link the target, or archive it for static libraries
+    /// # Arguments
+    /// * `dep_targets` - The targets that this target depends on
+    pub fn link_command(&self, dep_targets: &Vec<Target>) -> Vec<String> {
+        let mut objs = Vec::new();
+        let build_dir = Self::build_dir(&self.build_config.profile);
         for src in &self.srcs {
             objs.push(&src.obj_name);
         }
 
-        let mut cmd = String::new();
-        cmd.push_str(&self.build_config.compiler);
-        cmd.push_str(" -o ");
-        cmd.push_str(&self.bin_path);
-        if self.target_config.typ == "dll" {
-            cmd.push_str(" -shared ");
+        if self.target_config.typ == "static" {
+            return Self::archive_command(&self.bin_path, objs);
         }
 
-        for obj in objs {
-            cmd.push(' ');
-            cmd.push_str(obj);
+        let mut args = cmds::split_args(self.build_config.compiler_for(!self.needs_cxx_linker()));
+        args.push("-o".to_string());
+        args.push(self.bin_path.clone());
+        if self.target_config.typ == "dll" {
+            args.push("-shared".to_string());
         }
-        cmd.push(' ');
-        cmd.push_str(&self.target_config.cflags);
-        cmd.push(' ');
-        for dep_target in dep_targets {
-            cmd.push_str(" -I");
-            cmd.push_str(&dep_target.target_config.include_dir);
-            cmd.push(' ');
 
-            let lib_name = dep_target.target_config.name.clone();
-            let lib_name = lib_name.replace("lib", "-l");
-            cmd.push_str(&lib_name);
-            cmd.push(' ');
+        args.extend(objs.into_iter().cloned());
+        let profile = self.build_config.active_profile();
+        args.extend(self.include_flags());
+        args.extend(cmds::split_args(&self.target_config.cflags));
+        args.extend(cmds::split_args(&profile.cflags));
+        for dep_target in dep_targets {
+            Self::push_dep_lib(&mut args, dep_target);
         }
 
         for package in self.packages {
             for target in &package.target_configs {
-                cmd.push_str(" -I");
-                cmd.push_str(&target.include_dir);
-                cmd.push(' ');
-
-                let lib_name = target.name.clone();
-                let lib_name = lib_name.replace("lib", "-l");
-                cmd.push_str(&lib_name);
-                cmd.push(' ');
+                self.push_package_lib(&mut args, target, &package.target_configs);
             }
         }
 
         if self.packages.len() + self.dependant_libs.len() > 0 {
-            cmd.push_str("-L");
-            cmd.push_str(Self::build_dir());
+            args.push(format!("-L{}", build_dir));
 
             #[cfg(target_os = "linux")]
-            cmd.push_str(" -Wl,-rpath,\'$ORIGIN\' ");
+            args.push("-Wl,-rpath,$ORIGIN".to_string());
             #[cfg(target_os = "android")]
-            cmd.push_str(" -Wl,-rpath,\'$ORIGIN\' ");
+            args.push("-Wl,-rpath,$ORIGIN".to_string());
+        }
+        args.extend(cmds::split_args(&self.target_config.libs));
+        args.extend(self.pkg_config().libs);
+        args.extend(cmds::split_args(&profile.ldflags));
+        args
+    }
 
-            cmd.push(' ');
+    /// Links the target with its link command
+    /// Returns the diagnostics of the linker if it failed
+    /// # Arguments
+    /// * `args` - The arguments returned by link_command
+    pub fn link(&self, args: &[String]) -> Result<(), String> {
+        let cmd = cmds::join_args(args);
+        let build_dir = Self::build_dir(&self.build_config.profile);
+        if !Path::new(&build_dir).exists() {
+            fs::create_dir_all(&build_dir)
+                .map_err(|why| format!("Couldn't create build dir: {}", why))?;
+        }
+        //ar only adds and replaces members, so start from a fresh archive
+        if self.target_config.typ == "static" && Path::new(&self.bin_path).exists() {
+            fs::remove_file(&self.bin_path)
+                .map_err(|why| format!("Couldn't remove old archive: {}", why))?;
         }
-        cmd.push_str(&self.target_config.libs);
 
         log(
             LogLevel::Info,
             &format!("Linking target: {}", &self.target_config.name),
         );
-        log(LogLevel::Info, &format!("  Command: {}", &cmd));
-        let output = Command::new("sh")
-            .arg("-c")
-            .arg(&cmd)
-            .output()
-            .expect("failed to execute process");
+        log(LogLevel::Info, &format!("  Command: {}", cmd));
+        let output = cmds::execute(args).map_err(|why| {
+            log(
+                LogLevel::Error,
+                &format!("  Linking failed: {}", &self.target_config.name),
+            );
+            format!("Could not run {}: {}", cmd, why)
+        })?;
         if output.status.success() {
             log(LogLevel::Info, "  Linking successful");
-            hasher::save_hashes_to_file(&self.hash_file_path, &self.path_hash);
+            Ok(())
         } else {
-            log(LogLevel::Error, "  Linking failed");
-            log(LogLevel::Error, &format!("  Command: {}", &cmd));
             log(
                 LogLevel::Error,
-                &format!("  Error: {}", String::from_utf8_lossy(&output.stderr)),
+                &format!("  Linking failed: {}", &self.target_config.name),
             );
-            std::process::exit(1);
+            log(LogLevel::Error, &format!("  Command: {}", cmd));
+            Err(String::from_utf8_lossy(&output.stderr).to_string())
         }
     }
 
-    /// Generates the compile_commands.json file for a Src
-    fn gen_cc(&self, src: &Src) -> String {
-        let mut cc = String::new();
-        cc.push_str("{\n");
-        if self.build_config.compiler == "clang++" || self.build_config.compiler == "g++" {
-            cc.push_str("\t\"command\": \"c++");
-            if self.build_config.cppstandard.is_some() {
-                cc.push_str(" -std=");
-                cc.push_str(self.build_config.cppstandard.as_ref().unwrap());
-            } else {
-                cc.push_str(" -std=c++17");
-            }
-        } else if self.build_config.compiler == "clang" || self.build_config.compiler == "gcc" {
-            cc.push_str("\t\"command\": \"cc");
-            if self.build_config.cstandard.is_some() {
-                cc.push_str(" -std=");
-                cc.push_str(self.build_config.cstandard.as_ref().unwrap());
-            } else {
-                cc.push_str(" -std=c11");
+    /// Returns true if the target has to be linked with the C++ driver
+    /// That is the case when it or any of its dependencies has C++ sources,
+    /// package libraries are assumed to be C++
+    pub fn needs_cxx_linker(&self) -> bool {
+        self.srcs.iter().any(|src| !src.is_c())
+            || self.dependant_libs.iter().any(|dep| dep.needs_cxx_linker())
+            || self
+                .packages
+                .iter()
+                .any(|package| !package.target_configs.is_empty())
+    }
+
+    /// Returns the include dirs this target exports to its dependants
+    /// These are its public include dirs and the ones its own deps export
+    pub fn public_include_dirs(&self) -> Vec<String> {
+        let mut include_dirs = self.target_config.public_include_dirs.clone();
+        for dep_target in &self.dependant_libs {
+            include_dirs.append(&mut dep_target.public_include_dirs());
+        }
+        include_dirs.into_iter().unique().collect()
+    }
+
+    //returns every include dir used to compile the target, its private and
+    //public ones followed by the ones exported by its deps and packages
+    fn collect_include_dirs(&self) -> Vec<String> {
+        let mut include_dirs = self.target_config.include_dirs.clone();
+        include_dirs.append(&mut self.public_include_dirs());
+        for package in self.packages {
+            for target in &package.target_configs {
+                include_dirs.append(&mut target.public_include_dirs.clone());
             }
-        } else {
-            log(
-                LogLevel::Warn,
-                &format!("Compiler: {} is not supported", &self.build_config.compiler),
-            );
-            log(LogLevel::Info, "Assuming c++ compiler ends with `++`");
-            if self.build_config.compiler.ends_with("++") {
-                cc.push_str("\t\"command\": \"c++");
-                if self.build_config.cppstandard.is_some() {
-                    cc.push_str(" -std=");
-                    cc.push_str(self.build_config.cppstandard.as_ref().unwrap());
-                } else {
-                    cc.push_str(" -std=c++17");
-                }
-            } else {
-                cc.push_str("\t\"command\": \"cc");
-                if self.build_config.cstandard.is_some() {
-                    cc.push_str(" -std=");
-                    cc.push_str(self.build_config.cstandard.as_ref().unwrap());
-                } else {
-                    cc.push_str(" -std=c11");
+        }
+        for package in &self.build_config.packages {
+            let name = package
+                .split_whitespace()
+                .next()
+                .unwrap()
+                .split('/')
+                .next_back()
+                .unwrap()
+                .replace(',', "");
+            include_dirs.push(format!(".bld_cpp/includes/{}", name));
+        }
+        include_dirs
+            .into_iter()
+            .map(|dir| dir.replace('\\', "/"))
+            .unique()
+            .collect()
+    }
+
+    /// Returns the flags of the pkg-config modules of the target
+    pub fn pkg_config(&self) -> pkg_config::PkgFlags {
+        pkg_config::resolve(&self.target_config.pkg_config, &self.target_config.name)
+    }
+
+    /// Returns the include dirs of the target as compiler flags
+    pub fn include_flags(&self) -> Vec<String> {
+        self.include_dirs
+            .iter()
+            .map(|include_dir| format!("-I{}", include_dir))
+            .collect()
+    }
+
+    /// Returns the compiler flags this target gets from its dependencies
+    /// These are the flags of interface targets and their own dependencies
+    pub fn dependant_cflags(&self) -> Vec<String> {
+        fn push_interfaces<'b>(interfaces: &mut Vec<&'b TargetConfig>, dep_target: &Target<'b>) {
+            if dep_target.target_config.typ == "interface" {
+                interfaces.push(dep_target.target_config);
+                for dep in &dep_target.dependant_libs {
+                    push_interfaces(interfaces, dep);
                 }
             }
         }
-        cc.push_str(" -c -o ");
-        cc.push_str(&src.obj_name);
-        cc.push_str(" -I");
-        cc.push_str(&self.target_config.include_dir);
 
-        for lib in &self.dependant_libs {
-            cc.push_str(" -I");
-            cc.push_str(&lib.target_config.include_dir);
+        let mut interfaces = Vec::new();
+        for dep_target in &self.dependant_libs {
+            push_interfaces(&mut interfaces, dep_target);
         }
-        for pack in self.packages {
-            for tgtg in &pack.target_configs {
-                cc.push_str(" -I");
-                cc.push_str(&tgtg.include_dir);
+        for package in self.packages {
+            for target in &package.target_configs {
+                if target.typ == "interface" {
+                    interfaces.push(target);
+                }
             }
         }
 
-        cc.push(' ');
-        let cflags = &self.target_config.cflags;
+        //an interface reached through several deps passes its flags once
+        interfaces
+            .into_iter()
+            .unique_by(|target| *target as *const TargetConfig)
+            .flat_map(|target| {
+                let mut flags = cmds::split_args(&target.cflags);
+                flags.extend(pkg_config::resolve(&target.pkg_config, &target.name).cflags);
+                flags
+            })
+            .collect()
+    }
 
-        let subcmds = cflags.split('`').collect::<Vec<&str>>();
-        // Take even entries are non-subcmds and odd entries are subcmds
-        let (subcmds, non_subcmds): (Vec<String>, String) = subcmds.iter().enumerate().fold(
-            (Vec::new(), String::new()),
-            |(mut subcmds, mut non_subcmds), (i, subcmd)| {
-                if i % 2 != 0 {
-                    subcmds.push(subcmd.to_string());
-                } else {
-                    non_subcmds.push_str(subcmd);
-                    non_subcmds.push(' ');
+    //returns the flags every source of the target is compiled with, its
+    //include dirs, the cflags of its deps, its own and the profile's and its defines
+    fn compile_flags(&self) -> Vec<String> {
+        let mut flags = self.include_flags();
+        flags.extend(self.dependant_cflags());
+        flags.extend(cmds::split_args(&self.target_config.cflags));
+        flags.extend(self.pkg_config().cflags);
+        flags.extend(cmds::split_args(&self.build_config.active_profile().cflags));
+        flags.extend(self.defines().iter().map(Define::to_flag));
+        flags
+    }
+
+    /// Returns the defines the sources of this target are compiled with
+    /// The build defines come first, then the ones of interface deps and
+    /// packages, then the target's own and finally the profile's
+    pub fn defines(&self) -> Vec<Define> {
+        fn push_dep_defines(defines: &mut Vec<Define>, dep_target: &Target) {
+            if dep_target.target_config.typ == "interface" {
+                for dep in &dep_target.dependant_libs {
+                    push_dep_defines(defines, dep);
                 }
-                (subcmds, non_subcmds)
-            },
-        );
+                defines.extend(dep_target.target_config.defines.iter().cloned());
+            }
+        }
 
-        cc.push_str(&non_subcmds);
-
-        for subcmd in subcmds {
-            let cmd_output = Command::new("sh")
-                .arg("-c")
-                .arg(&subcmd)
-                .output()
-                .expect("failed to execute process");
-            if cmd_output.status.success() {
-                let stdout = String::from_utf8_lossy(&cmd_output.stdout);
-                let stdout = stdout.replace('\n', " ");
-                cc.push_str(&stdout);
-            } else {
-                let stderr = String::from_utf8_lossy(&cmd_output.stderr);
-                log(
-                    LogLevel::Error,
-                    &format!("Failed to execute subcmd: {}", &subcmd),
-                );
-                log(LogLevel::Error, &format!("  Stderr: {}", stderr));
-                std::process::exit(1);
+        let mut defines = self.build_config.defines.clone();
+        for dep_target in &self.dependant_libs {
+            push_dep_defines(&mut defines, dep_target);
+        }
+        for package in self.packages {
+            for target in &package.target_configs {
+                if target.typ == "interface" {
+                    defines.extend(target.defines.iter().cloned());
+                }
             }
         }
+        defines.extend(self.target_config.defines.iter().cloned());
+        defines.extend(self.build_config.active_profile().defines);
+        Define::merge(defines)
+    }
 
-        #[cfg(target_os = "linux")]
-        if self.target_config.typ == "dll" {
-            cc.push_str("-fPIC ");
+    //appends the link arguments of a dependency to the command
+    //static libraries are linked by path, followed by their own libs and deps
+    fn push_dep_lib(args: &mut Vec<String>, dep_target: &Target) {
+        if dep_target.target_config.typ == "static" {
+            args.push(dep_target.bin_path.clone());
+            for dep in &dep_target.dependant_libs {
+                Self::push_dep_lib(args, dep);
+            }
+            args.extend(cmds::split_args(&dep_target.target_config.libs));
+            args.extend(dep_target.pkg_config().libs);
+        } else if dep_target.target_config.typ == "interface" {
+            //interface targets have no binary, only flags and their own deps
+            args.extend(cmds::split_args(&dep_target.target_config.cflags));
+            for dep in &dep_target.dependant_libs {
+                Self::push_dep_lib(args, dep);
+            }
+            args.extend(cmds::split_args(&dep_target.target_config.libs));
+            args.extend(dep_target.pkg_config().libs);
+        } else {
+            args.push(dep_target.target_config.name.replacen("lib", "-l", 1));
         }
-        #[cfg(target_os = "android")]
-        if self.target_config.typ == "dll" {
-            cc.push_str("-fPIC ");
+    }
+
+    //appends the link arguments of a package target to the command, like
+    //push_dep_lib does for local deps. Its deps are targets of the same package
+    fn push_package_lib(
+        &self,
+        args: &mut Vec<String>,
+        target: &TargetConfig,
+        package_targets: &[TargetConfig],
+    ) {
+        let push_deps = |args: &mut Vec<String>| {
+            for dep in package_targets
+                .iter()
+                .filter(|dep| target.deps.contains(&dep.name))
+            {
+                self.push_package_lib(args, dep, package_targets);
+            }
+        };
+        let pkg_libs = pkg_config::resolve(&target.pkg_config, &target.name).libs;
+        if target.typ == "static" {
+            args.push(Self::bin_path_for(target, &self.build_config.profile));
+            push_deps(args);
+            args.extend(cmds::split_args(&target.libs));
+            args.extend(pkg_libs);
+        } else if target.typ == "interface" {
+            args.extend(cmds::split_args(&target.cflags));
+            push_deps(args);
+            args.extend(cmds::split_args(&target.libs));
+            args.extend(pkg_libs);
+        } else {
+            args.push(target.name.replacen("lib", "-l", 1));
         }
+    }
 
-        cc.push_str(&src.path);
-        cc.push_str("\",\n");
-        let mut dirent = String::new();
-        dirent.push_str("\t\"directory\": \"");
-        dirent.push_str(
-            &std::env::current_dir()
-                .unwrap()
-                .to_str()
-                .unwrap()
-                .replace('\\', "/"),
-        );
-        dirent.push_str("\",\n");
-        let dirent = dirent.replace('/', "\\\\").replace("\\\\.\\\\", "\\\\");
-        cc.push_str(&dirent);
-        let mut fileent = String::new();
-        fileent.push_str("\t\"file\": \"");
-        fileent.push_str(
-            &std::env::current_dir()
-                .unwrap()
-                .to_str()
-                .unwrap()
-                .replace('\\', "/"),
+    //returns the arguments that archive the object files of a static library
+    fn archive_command(bin_path: &str, objs: Vec<&String>) -> Vec<String> {
+        let mut args = vec!["ar".to_string(), "rcs".to_string(), bin_path.to_string()];
+        args.extend(objs.into_iter().cloned());
+        args
+    }
+
+    /// Generates the compile_commands.json entry for a Src
+    fn gen_cc(&self, src: &Src) -> String {
+        let mut command = if src.is_c() { "cc" } else { "c++" }.to_string();
+        command.push_str(" -std=");
+        command.push_str(
+            &self
+                .target_config
+                .standard_for(self.build_config, src.is_c()),
         );
-        fileent.push('/');
-        fileent.push_str(&src.path);
-        fileent.push('\"');
-        let fileent = fileent.replace('/', "\\\\").replace("\\\\.\\\\", "\\\\");
+        command.push_str(" -c -o ");
+        command.push_str(&src.obj_name);
+        command.push(' ');
+        //the flags are quoted for the shell, serde_json escapes them for json
+        command.push_str(&cmds::join_args(&self.compile_flags()));
+        command.push(' ');
 
-        cc.push_str(&fileent);
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if self.target_config.typ == "dll" || self.target_config.typ == "static" {
+            command.push_str("-fPIC ");
+        }
 
-        cc.push_str("\n}");
-        #[cfg(target_os = "linux")]
-        return cc.replace("\\\\", "/");
-        #[cfg(target_os = "android")]
-        return cc.replace("\\\\", "/");
+        command.push_str(&src.path);
+        let directory = std::env::current_dir()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .replace('\\', "/");
+        let file = format!("{}/{}", directory, src.path).replace("/./", "/");
         #[cfg(target_os = "windows")]
-        return cc;
-    }
-    //returns a vector of source files in the given root path
-    fn get_srcs(&mut self, root_path: &str, _target_config: &'a TargetConfig) -> Vec<Src> {
-        let root_dir = PathBuf::from(root_path);
-        let mut srcs: Vec<Src> = Vec::new();
-        let root_entries = std::fs::read_dir(root_dir).unwrap_or_else(|_| {
-            log(
-                LogLevel::Error,
-                &format!("Could not read directory: {}", root_path),
-            );
-            std::process::exit(1);
+        let (directory, file) = (directory.replace('/', "\\"), file.replace('/', "\\"));
+        let entry = serde_json::json!({
+            "command": command,
+            "directory": directory,
+            "file": file,
         });
-        for entry in root_entries {
-            let entry = entry.unwrap();
-            if entry.path().is_dir() {
-                let path = entry.path().to_str().unwrap().to_string();
-                srcs.append(&mut self.get_srcs(&path, _target_config));
-            } else {
-                if !entry.path().to_str().unwrap().ends_with(".cpp")
-                    && !entry.path().to_str().unwrap().ends_with(".c")
-                    && !entry.path().to_str().unwrap().ends_with(".cu")
-                {
-                    continue;
-                }
-                let path = entry
-                    .path()
-                    .to_str()
-                    .unwrap()
-                    .to_string()
-                    .replace('\\', "/");
-                self.add_src(path);
-            }
+        serde_json::to_string_pretty(&entry).unwrap()
+    }
+    //adds all source files selected by the target config to the target
+    //recorded_deps are the headers the compiler found in the last build
+    fn get_srcs(
+        &mut self,
+        target_config: &'a TargetConfig,
+        recorded_deps: &HashMap<String, Vec<String>>,
+    ) {
+        for path in target_config.get_src_files(false) {
+            self.add_src(path, recorded_deps);
+        }
+        //the flags are split once for the whole target
+        let flags = self.compile_flags();
+        for src in self.srcs.iter_mut() {
+            src.compile_args = src.compile_command(self.build_config, self.target_config, &flags);
+            //the command as it is shown and recorded
+            src.compile_cmd = cmds::join_args(&src.compile_args);
         }
-        srcs
     }
 
     //adds a source file to the target
-    fn add_src(&mut self, path: String) {
+    //its headers come from the last build, the include scanner is only used
+    //for sources that were never compiled
+    fn add_src(&mut self, path: String, recorded_deps: &HashMap<String, Vec<String>>) {
         let name = Target::get_src_name(&path);
-        let obj_name = self.get_src_obj_name(&name);
-        let dependant_includes = self.get_dependant_includes(&path);
-        if dependant_includes.is_err() {
-            log(
-                LogLevel::Error,
-                &format!("Could find the include files for: {}", &path),
-            );
-            let error_chain = dependant_includes.err().unwrap();
-            log(LogLevel::Error, "Error chain:");
-            for error in error_chain.into_iter().rev() {
-                log(LogLevel::Error, &format!("  {}", error));
-            }
-            std::process::exit(1);
-        }
-        let dependant_includes = dependant_includes.unwrap();
-        let bin_path = self.bin_path.clone();
+        let obj_name = self.get_src_obj_name(&path);
+        let dependant_includes = match recorded_deps.get(&path) {
+            Some(includes) => includes.clone(),
+            None => self.get_dependant_includes(&path),
+        };
         self.srcs
-            .push(Src::new(path, name, obj_name, bin_path, dependant_includes));
+            .push(Src::new(path, name, obj_name, dependant_includes));
     }
 
     //returns the file name without the extension from the path
@@ -629,46 +1058,58 @@ impl<'a> Target<'a> {
     }
 
     //return the object file name for the given source file
-    fn get_src_obj_name(&self, src_name: &str) -> String {
-        let mut obj_name = String::new();
-        obj_name.push_str(Self::obj_dir());
-        obj_name.push('/');
-        obj_name.push_str(&self.target_config.name);
-        obj_name.push_str(src_name);
-        obj_name.push_str(".o");
-        obj_name
+    //objects mirror the source tree under a directory per target, so
+    //src/a/util.cpp becomes <obj_dir>/<target>/src/a/util.cpp.o
+    fn get_src_obj_name(&self, src_path: &str) -> String {
+        let rel_path = Path::new(src_path)
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(part) => Some(part.to_str().unwrap().to_string()),
+                //sources outside the project must stay inside the obj dir
+                Component::ParentDir => Some("__".to_string()),
+                _ => None,
+            })
+            .collect::<Vec<String>>()
+            .join("/");
+        format!("{}/{}.o", self.obj_dir_of_target(), rel_path)
     }
 
     //returns a vector of .h or .hpp files the given C/C++ depends on
-    fn get_dependant_includes(&mut self, path: &str) -> Result<Vec<String>, Vec<String>> {
+    //includes are looked up next to the file and in the include dirs,
+    //the ones that are not found are left to the compiler's dependency file
+    fn get_dependant_includes(&mut self, path: &str) -> Vec<String> {
         let mut result = Vec::new();
-        let include_substrings = self.get_include_substrings(path);
-        if include_substrings.is_none() {
-            return Err(vec![path.to_string()]);
-        }
-        let include_substrings = include_substrings.unwrap();
-        if include_substrings.is_empty() {
-            return Ok(result);
-        }
+        let include_substrings = self.get_include_substrings(path).unwrap_or_default();
+        let file_dir = Path::new(path)
+            .parent()
+            .map(|dir| dir.to_str().unwrap().to_string())
+            .unwrap_or_default();
         for include_substring in include_substrings {
-            let dep_path = format!("{}/{}", &self.target_config.include_dir, &include_substring);
-            if self.dependant_includes.contains_key(&dep_path) {
+            let dep_path = std::iter::once(&file_dir)
+                .chain(self.include_dirs.iter())
+                .map(|include_dir| format!("{}/{}", include_dir, &include_substring))
+                .find(|dep_path| Path::new(dep_path).is_file());
+            let dep_path = match dep_path {
+                Some(dep_path) => dep_path,
+                None => continue,
+            };
+            if result.contains(&dep_path) {
+                continue;
+            }
+            result.push(dep_path.clone());
+            if let Some(child_includes) = self.dependant_includes.get(&dep_path) {
+                result.extend(child_includes.iter().cloned());
                 continue;
             }
+            //mark the header as seen before descending, so include cycles end
+            self.dependant_includes.insert(dep_path.clone(), Vec::new());
             let child_includes = self.get_dependant_includes(&dep_path);
-            if child_includes.is_err() {
-                let mut error_chain = child_includes.err().unwrap();
-                error_chain.push(path.to_string());
-                return Err(error_chain);
-            }
-            let mut child_includes = child_includes.unwrap();
-            result.append(&mut child_includes);
-            result.push(dep_path);
             self.dependant_includes
-                .insert(include_substring, result.clone());
+                .insert(dep_path, child_includes.clone());
+            result.extend(child_includes);
         }
 
-        Ok(result.into_iter().unique().collect())
+        result.into_iter().unique().collect()
     }
 
     //returns a vector of strings that are the include substrings
@@ -685,11 +1126,41 @@ impl<'a> Target<'a> {
         let lines = buf.lines();
         let mut include_substrings = Vec::new();
         for line in lines {
-            if line.starts_with("#include \"") {
-                let include_path = line.split('\"').nth(1).unwrap().to_owned();
-                include_substrings.push(include_path);
+            let line = line.trim_start();
+            let directive = match line.strip_prefix('#') {
+                Some(directive) => directive.trim_start(),
+                None => continue,
+            };
+            if let Some(include) = directive.strip_prefix("include") {
+                if let Some(include_path) = include.trim_start().strip_prefix('"') {
+                    if let Some((include_path, _)) = include_path.split_once('"') {
+                        include_substrings.push(include_path.to_owned());
+                    }
+                }
             }
         }
         Some(include_substrings)
     }
 }
+
+//removes the files under dir that are not in keep, and the directories
+//left empty
+fn remove_files_except(dir: &Path, keep: &HashSet<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            remove_files_except(&path, keep);
+            //only succeeds if the directory is empty
+            let _ = fs::remove_dir(&path);
+        } else if !keep.contains(&path) {
+            log(
+                LogLevel::Log,
+                &format!("Removing stale object: {}", path.display()),
+            );
+            let _ = fs::remove_file(&path);
+        }
+    }
+}
@@ -0,0 +1,283 @@
+//Records when the jobs of a build start and end, and writes them as a Chrome
+//trace, which chrome://tracing and Perfetto open, along with a summary of the
+//slowest files and targets and of the critical path of the build graph
+use crate::utils::log::{log, LogLevel};
+use std::collections::HashMap;
+use std::fs;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// Where the Chrome trace is written
+pub const TRACE_PATH: &str = "./.bld_cpp/timings.json";
+/// Where the summary is written
+pub const SUMMARY_PATH: &str = "./.bld_cpp/timings.txt";
+
+//how many of the slowest files and targets the summary lists
+const SLOWEST: usize = 10;
+
+//when recording started, set only with --timings
+static START: OnceLock<Instant> = OnceLock::new();
+//everything recorded so far
+static EVENTS: Mutex<Vec<Event>> = Mutex::new(Vec::new());
+//the longest chain of jobs that waited on one another
+static CRITICAL_PATH: Mutex<Vec<Event>> = Mutex::new(Vec::new());
+
+/// Something the build spent time on
+#[derive(Clone)]
+pub struct Event {
+    /// What kind of work it is, like compile, link or package
+    pub category: &'static str,
+    /// What it worked on, like the path of a source or the name of a target
+    pub name: String,
+    /// The target it belongs to, if any
+    pub target: Option<String>,
+    /// The thread it ran on, 0 for the main thread and then one per worker
+    pub lane: usize,
+    /// When it started
+    pub start: Instant,
+    /// When it ended
+    pub end: Instant,
+}
+
+impl Event {
+    /// Returns how long it took
+    pub fn duration(&self) -> Duration {
+        self.end - self.start
+    }
+}
+
+/// Starts recording, nothing is recorded before it is called
+pub fn enable() {
+    START.get_or_init(Instant::now);
+}
+
+/// Returns true if the build is being recorded
+pub fn enabled() -> bool {
+    START.get().is_some()
+}
+
+/// Records an event, if the build is being recorded
+/// # Arguments
+/// * `event` - What was worked on and when
+pub fn record(event: Event) {
+    if enabled() {
+        EVENTS.lock().unwrap().push(event);
+    }
+}
+
+/// Runs f on the main thread and records how long it took, if the build is
+/// being recorded
+/// # Arguments
+/// * `category` - What kind of work f does
+/// * `name` - What f works on
+/// * `f` - The work
+pub fn time<T>(category: &'static str, name: &str, f: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = f();
+    record_since(category, name, start);
+    result
+}
+
+/// Records work done on the main thread from start until now, if the build
+/// is being recorded
+/// # Arguments
+/// * `category` - What kind of work it was
+/// * `name` - What it worked on
+/// * `start` - When it started
+pub fn record_since(category: &'static str, name: &str, start: Instant) {
+    record(Event {
+        category,
+        name: name.to_string(),
+        target: None,
+        lane: 0,
+        start,
+        end: Instant::now(),
+    });
+}
+
+/// Records the critical path of the build, if the build is being recorded
+/// # Arguments
+/// * `events` - The longest chain of jobs that depend on one another, in order
+pub fn record_critical_path(events: Vec<Event>) {
+    if enabled() {
+        *CRITICAL_PATH.lock().unwrap() = events;
+    }
+}
+
+/// Writes the trace and the summary of everything recorded, once the build is done
+/// Does nothing if the build is not being recorded
+pub fn write() {
+    let Some(start) = START.get() else {
+        return;
+    };
+    let critical_path = CRITICAL_PATH.lock().unwrap().clone();
+    let mut events = EVENTS.lock().unwrap().clone();
+    events.sort_by_key(|event| event.start);
+    let total = start.elapsed();
+
+    let trace_events = events
+        .iter()
+        .map(|event| {
+            let args = match &event.target {
+                Some(target) => format!(",\"args\":{{\"target\":{}}}", json_str(target)),
+                None => String::new(),
+            };
+            format!(
+                "{{\"name\":{},\"cat\":\"{}\",\"ph\":\"X\",\"ts\":{},\"dur\":{},\"pid\":1,\"tid\":{}{}}}",
+                json_str(&event.name),
+                event.category,
+                (event.start - *start).as_micros(),
+                event.duration().as_micros(),
+                event.lane,
+                args
+            )
+        })
+        .collect::<Vec<String>>();
+    let lanes = events.iter().map(|event| event.lane).max().unwrap_or(0);
+    let lane_names = (0..=lanes).map(|lane| {
+        let name = match lane {
+            0 => "main".to_string(),
+            lane => format!("worker {}", lane),
+        };
+        format!(
+            "{{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":1,\"tid\":{},\"args\":{{\"name\":\"{}\"}}}}",
+            lane, name
+        )
+    });
+    let trace = format!(
+        "{{\"traceEvents\":[\n{}\n],\"displayTimeUnit\":\"ms\"}}\n",
+        lane_names
+            .chain(trace_events)
+            .collect::<Vec<String>>()
+            .join(",\n")
+    );
+    fs::write(TRACE_PATH, trace).unwrap_or_else(|why| {
+        log(
+            LogLevel::Error,
+            &format!("Could not write {}: {}", TRACE_PATH, why),
+        );
+        std::process::exit(1);
+    });
+
+    let critical = critical_path.iter().map(Event::duration).sum::<Duration>();
+    let summary = summary(&events, &critical_path, total);
+    fs::write(SUMMARY_PATH, summary).unwrap_or_else(|why| {
+        log(
+            LogLevel::Error,
+            &format!("Could not write {}: {}", SUMMARY_PATH, why),
+        );
+        std::process::exit(1);
+    });
+    log(
+        LogLevel::Log,
+        &format!(
+            "Build took {}, critical path {}, timings written to {} and {}",
+            secs(total),
+            secs(critical),
+            TRACE_PATH,
+            SUMMARY_PATH
+        ),
+    );
+}
+
+//returns the text summary of the build
+fn summary(events: &[Event], critical_path: &[Event], total: Duration) -> String {
+    let mut lines = vec![format!("Build took {}", secs(total))];
+    let busy = |category: &str| {
+        events
+            .iter()
+            .filter(|event| event.category == category)
+            .map(Event::duration)
+            .sum::<Duration>()
+    };
+    for category in ["package", "check", "compile", "link", "generate"] {
+        let count = events.iter().filter(|e| e.category == category).count();
+        if count > 0 {
+            lines.push(format!(
+                "  {:<8} {:>4} job(s) {:>10}",
+                category,
+                count,
+                secs(busy(category))
+            ));
+        }
+    }
+
+    lines.push(String::new());
+    lines.push(format!(
+        "Critical path: {} in {} job(s)",
+        secs(critical_path.iter().map(Event::duration).sum()),
+        critical_path.len()
+    ));
+    for event in critical_path {
+        lines.push(format!(
+            "  {:>10}  {} {}",
+            secs(event.duration()),
+            event.category,
+            event.name
+        ));
+    }
+
+    let mut compiles = events
+        .iter()
+        .filter(|event| event.category == "compile")
+        .collect::<Vec<&Event>>();
+    compiles.sort_by_key(|event| std::cmp::Reverse(event.duration()));
+    lines.push(String::new());
+    lines.push("Slowest files:".to_string());
+    for event in compiles.iter().take(SLOWEST) {
+        lines.push(format!(
+            "  {:>10}  {} ({})",
+            secs(event.duration()),
+            event.name,
+            event.target.as_deref().unwrap_or("")
+        ));
+    }
+
+    //the time of a target is the time of its compiles and of its link
+    let mut targets: HashMap<&str, (Duration, Duration)> = HashMap::new();
+    for event in events {
+        let Some(target) = &event.target else {
+            continue;
+        };
+        match event.category {
+            "compile" => targets.entry(target).or_default().0 += event.duration(),
+            "link" => targets.entry(target).or_default().1 += event.duration(),
+            _ => {}
+        }
+    }
+    let mut targets = targets.into_iter().collect::<Vec<_>>();
+    targets.sort_by_key(|(name, (compile, link))| (std::cmp::Reverse(*compile + *link), *name));
+    lines.push(String::new());
+    lines.push("Slowest targets (compile + link):".to_string());
+    for (name, (compile, link)) in targets.iter().take(SLOWEST) {
+        lines.push(format!(
+            "  {:>10}  {} ({} + {})",
+            secs(*compile + *link),
+            name,
+            secs(*compile),
+            secs(*link)
+        ));
+    }
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+//formats a duration in seconds with millisecond precision
+fn secs(duration: Duration) -> String {
+    format!("{:.3}s", duration.as_secs_f64())
+}
+
+//returns a string as a json string literal
+fn json_str(s: &str) -> String {
+    let mut json = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
@@ -7,6 +7,7 @@ enum ConfigParam {
     DefaultCompiler(String),
     DefaultLanguage(String),
     License(String),
+    MaxJobs(usize),
 }
 
 fn set_config_param(param: ConfigParam, config_file: &PathBuf) {
@@ -21,6 +22,9 @@ fn set_config_param(param: ConfigParam, config_file: &PathBuf) {
         ConfigParam::License(value) => {
             global_conf.license = value;
         }
+        ConfigParam::MaxJobs(value) => {
+            global_conf.max_jobs = Some(value);
+        }
     }
 
     std::fs::write(config_file, toml::to_string(&global_conf).unwrap()).unwrap();
@@ -31,6 +35,8 @@ pub struct GlobalConfig {
     default_compiler: String,
     default_language: String,
     license: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_jobs: Option<usize>,
 }
 
 impl GlobalConfig {
@@ -70,6 +76,18 @@ impl GlobalConfig {
                     std::process::exit(1);
                 }
             }
+            "max_jobs" => match value.parse::<usize>() {
+                Ok(jobs) if jobs > 0 => {
+                    set_config_param(ConfigParam::MaxJobs(jobs), config);
+                }
+                _ => {
+                    log(
+                        LogLevel::Error,
+                        "Invalid number of jobs. See `builder-cpp config --help` for more info",
+                    );
+                    std::process::exit(1);
+                }
+            },
             _ => {
                 log(
                     LogLevel::Error,
@@ -97,6 +115,11 @@ impl GlobalConfig {
                 .unwrap()
                 .to_string(),
             license: config.get("license").unwrap().as_str().unwrap().to_string(),
+            max_jobs: config
+                .get("max_jobs")
+                .and_then(|jobs| jobs.as_integer())
+                .and_then(|jobs| usize::try_from(jobs).ok())
+                .filter(|jobs| *jobs > 0),
         }
     }
     pub fn get_default_compiler(&self) -> String {
@@ -110,4 +133,8 @@ impl GlobalConfig {
     pub fn get_license(&self) -> String {
         self.license.clone()
     }
+
+    pub fn get_max_jobs(&self) -> Option<usize> {
+        self.max_jobs
+    }
 }
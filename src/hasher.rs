@@ -1,138 +1,143 @@
 //! This module contains functions for hashing files and checking if they have changed.
 use crate::utils::log::{log, LogLevel};
-use sha1::{Digest, Sha1};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::fs::{File, OpenOptions};
-use std::io::{Read, Write};
-use std::path::Path;
+use std::fs::File;
+use std::io::Read;
+use std::sync::{Mutex, OnceLock};
+use std::time::UNIX_EPOCH;
+use xxhash_rust::xxh3::Xxh3;
+
+/// The hash of a file along with its modification time and size when it was hashed
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FileState {
+    pub hash: String,
+    pub mtime: u64,
+    pub size: u64,
+}
+
+//the states of the files hashed during this build, a state is reused as long
+//as the file keeps its modification time and size, so every file is hashed
+//once no matter how many sources include it
+static FILE_STATES: OnceLock<Mutex<HashMap<String, FileState>>> = OnceLock::new();
+
+//returns the modification time and size of a file, None if it does not exist
+fn stat(path: &str) -> Option<(u64, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|mtime| mtime.duration_since(UNIX_EPOCH).ok())
+        .map(|mtime| mtime.as_nanos() as u64)
+        .unwrap_or(0);
+    Some((mtime, metadata.len()))
+}
+
+/// Returns the state of a file, None if the file does not exist.
+/// The file is only hashed if it changed since it was last hashed in this build.
+/// Returns an error if the file exists but can not be read.
+/// # Arguments
+/// * `path` - The path of the file.
+pub fn file_state(path: &str) -> Result<Option<FileState>, String> {
+    let Some((mtime, size)) = stat(path) else {
+        return Ok(None);
+    };
+    let file_states = FILE_STATES.get_or_init(|| Mutex::new(HashMap::new()));
+    if let Some(state) = file_states.lock().unwrap().get(path) {
+        if state.mtime == mtime && state.size == size {
+            return Ok(Some(state.clone()));
+        }
+    }
+    let state = FileState {
+        hash: hash_file(path)?,
+        mtime,
+        size,
+    };
+    file_states
+        .lock()
+        .unwrap()
+        .insert(path.to_string(), state.clone());
+    Ok(Some(state))
+}
+
+//returns true if the file still has the modification time and size it was
+//recorded with, in which case its contents are not hashed again
+fn is_same_stat(path: &str, recorded: &FileState) -> bool {
+    stat(path) == Some((recorded.mtime, recorded.size))
+}
 
 // Hashes a file and returns the hash as a string.
-fn hash_file(path: &str) -> String {
-    let mut file = File::open(path).unwrap();
+fn hash_file(path: &str) -> Result<String, String> {
+    let mut file =
+        File::open(path).map_err(|why| format!("Failed to open file: {}: {}", path, why))?;
     const CHUNK_SIZE: usize = 1024 * 1024;
 
-    let mut limit = file
-        .metadata()
-        .unwrap_or_else(|why| {
-            log(
-                LogLevel::Error,
-                &format!("Failed to get length for file: {}", path),
-            );
-            log(LogLevel::Error, &format!("Error: {}", why));
-            std::process::exit(1);
-        })
-        .len();
-    let mut buffer = [0; CHUNK_SIZE];
-    let mut hasher = Sha1::new();
-
-    while limit > 0 {
-        let read_size = if limit < CHUNK_SIZE as u64 {
-            limit as usize
-        } else {
-            CHUNK_SIZE
-        };
-        let read = file.read(&mut buffer[0..read_size]).unwrap();
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let mut hasher = Xxh3::new();
+    loop {
+        let read = file
+            .read(&mut buffer)
+            .map_err(|why| format!("Failed to read file: {}: {}", path, why))?;
         if read == 0 {
             break;
         }
-        limit -= read as u64;
         hasher.update(&buffer[0..read]);
     }
-    let result = hasher.finalize();
-    let mut hash = String::new();
-    for byte in result {
-        hash.push_str(&format!("{:02x}", byte));
-    }
-    hash
+    Ok(format!("{:032x}", hasher.digest128()))
 }
 
 /// Returns the hash of a file if it exists in the path_hash.
 /// Otherwise returns None.
 /// # Arguments
 /// * `path` - The path of the file to get the hash of.
-/// * `path_hash` - The hashmap of paths and hashes.
-pub fn get_hash(path: &str, path_hash: &HashMap<String, String>) -> Option<String> {
-    if path_hash.contains_key(path) {
-        Some(path_hash.get(path).unwrap().to_string())
-    } else {
-        None
-    }
-}
-
-/// Loads the hashes from a file and returns them as a hashmap.
-/// # Arguments
-/// * `path` - The path of the file to load the hashes from.
-pub fn load_hashes_from_file(path: &str) -> HashMap<String, String> {
-    let mut path_hash: HashMap<String, String> = HashMap::new();
-    let path = Path::new(path);
-    if !path.exists() {
-        return path_hash;
-    }
-    let mut file = OpenOptions::new().read(true).open(path).unwrap();
-    let mut contents = String::new();
-    file.read_to_string(&mut contents).unwrap();
-    for line in contents.lines() {
-        if line.is_empty() {
-            continue;
-        }
-        let mut split = line.split(' ');
-        let path = split.next().unwrap();
-        let hash = split.next().unwrap();
-        path_hash.insert(path.to_string(), hash.to_string());
-    }
-    path_hash
-}
-
-/// Saves the hashes to a file.
-/// # Arguments
-/// * `path` - The path of the file to save the hashes to.
-/// * `path_hash` - The hashmap of paths and hashes.
-pub fn save_hashes_to_file(path: &str, path_hash: &HashMap<String, String>) {
-    let mut file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .open(path)
-        .unwrap_or_else(|_| {
-            log(LogLevel::Error, &format!("Failed to open file: {}", path));
-            std::process::exit(1);
-        });
-    for (path, hash) in path_hash {
-        let line = format!("{} {}\n", path, hash);
-        file.write_all(line.as_bytes()).unwrap();
-    }
+/// * `path_hash` - The hashmap of paths and file states.
+pub fn get_hash(path: &str, path_hash: &HashMap<String, FileState>) -> Option<String> {
+    path_hash.get(path).map(|state| state.hash.clone())
 }
 
 /// Checks if a file has changed.
+/// Files that kept their modification time and size are not hashed,
+/// a file that can not be read counts as changed.
 /// # Arguments
 /// * `path` - The path of the file to check.
-/// * `path_hash` - The hashmap of paths and hashes.
-pub fn is_file_changed(path: &str, path_hash: &HashMap<String, String>) -> bool {
-    let hash = get_hash(path, path_hash);
-    if hash.is_none() {
-        return true;
+/// * `path_hash` - The hashmap of paths and file states.
+pub fn is_file_changed(path: &str, path_hash: &HashMap<String, FileState>) -> bool {
+    //a header that was removed or renamed counts as changed
+    let recorded = match path_hash.get(path) {
+        Some(recorded) => recorded,
+        None => return true,
+    };
+    if is_same_stat(path, recorded) {
+        return false;
+    }
+    match file_state(path) {
+        Ok(Some(state)) => state.hash != recorded.hash,
+        _ => true,
     }
-    let hash = hash.unwrap();
-    let new_hash = hash_file(path);
-    hash != new_hash
 }
 
-/// Saves the hash of a file to the hashmap.
+/// Saves the state of a file to the hashmap.
+/// Returns an error if the file exists but can not be read.
 /// # Arguments
 /// * `path` - The path of the file to save the hash of.
-/// * `path_hash` - The hashmap of paths and hashes.
-pub fn save_hash(path: &str, path_hash: &mut HashMap<String, String>) {
-    let new_hash = hash_file(path);
-    let hash = get_hash(path, path_hash);
-    if hash.is_none() {
-        path_hash.insert(path.to_string(), new_hash);
-        return;
+/// * `path_hash` - The hashmap of paths and file states.
+pub fn save_hash(path: &str, path_hash: &mut HashMap<String, FileState>) -> Result<(), String> {
+    if let Some(recorded) = path_hash.get(path) {
+        if is_same_stat(path, recorded) {
+            return Ok(());
+        }
     }
-    let hash = hash.unwrap();
-    if hash != new_hash {
+    let new_state = match file_state(path)? {
+        Some(new_state) => new_state,
+        None => return Ok(()),
+    };
+    let hash = get_hash(path, path_hash);
+    if hash.is_some() && hash.as_ref() != Some(&new_state.hash) {
         log(
             LogLevel::Info,
             &format!("File changed, updating hash for file: {}", path),
         );
-        path_hash.insert(path.to_string(), new_hash);
     }
+    path_hash.insert(path.to_string(), new_state);
+    Ok(())
 }
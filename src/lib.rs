@@ -3,21 +3,22 @@
 //! This library automatically configures various targets in your project
 //! and gives an easy interface to grab packages from github.
 //!
-//! The library uses config_linux.toml or config_win32.toml file to configure the project.
+//! The library uses a builder_cpp.toml file to configure the project.
+//! The older config_linux.toml and config_win32.toml files are used if it does not exist.
 //!
 //! # Installation
 //! To install this library, you need to have rust installed on your system.
-//! ```no_run
+//! ```console
 //! cargo install builder_cpp
 //! ```
 //!
 //! # Examples
 //! To get the various flags that can be passed to builder_cpp
-//! ```no_run
+//! ```console
 //! builder_cpp -h
 //! ```
 //! or
-//! ```no_run
+//! ```console
 //! builder_cpp --help
 //! ```
 //!
@@ -25,7 +26,7 @@
 //! Optional keys in toml are packages in build and deps in targets
 //! Project contains an executable and a library from a github repo
 //! ```toml
-//! # config_linux.toml
+//! # builder_cpp.toml
 //![build]
 //!compiler = "g++"
 //!packages = ["Dr-42/Nomu_Engine, master"]
@@ -41,7 +42,7 @@
 //!```
 //! Projects contains a library and an executable
 //!```toml
-//! # config_win32.toml
+//! # builder_cpp.toml
 //![build]
 //!let compiler = "g++"
 //!build_dir = "./bin"
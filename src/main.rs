@@ -1,9 +1,11 @@
 use builder_cpp::{
     bin_flags,
+    builder::{sched::BuildOptions, timings},
     utils::log::{log, LogLevel},
 };
 use clap::{Parser, Subcommand};
 use directories::ProjectDirs;
+use std::num::NonZeroUsize;
 
 use builder_cpp::global_config::GlobalConfig;
 
@@ -16,9 +18,9 @@ struct Args {
     /// Clean the obj and bin intermediates
     #[arg(short, long)]
     clean: bool,
-    /// Run the executable
-    #[arg(short, long)]
-    run: bool,
+    /// Run an executable. The target name is needed only if the project has several
+    #[arg(short, long, value_name = "TARGET", num_args(0..=1))]
+    run: Option<Option<String>>,
 
     /// Initialize a new project. See `init --help` for more info
     #[command(subcommand)]
@@ -43,6 +45,29 @@ struct Args {
     /// Restore packages
     #[arg(long)]
     restore_packages: bool,
+
+    /// Build profile to use, like debug, release or one defined in the config
+    #[arg(long, default_value = "debug")]
+    profile: String,
+
+    /// Number of jobs to run at once. Defaults to BUILDER_CPP_JOBS, then to max_jobs of the global config, then to the number of cpus
+    #[arg(short, long, value_name = "N")]
+    jobs: Option<NonZeroUsize>,
+    /// Do not start new jobs while the load average is at or above this
+    #[arg(long, value_name = "LOAD")]
+    load_average: Option<f64>,
+    /// Keep building what does not depend on a failed file or target, and list every failure at the end
+    #[arg(short, long)]
+    keep_going: bool,
+    /// Print the compile and link commands of the build without running them
+    #[arg(long)]
+    dry_run: bool,
+    /// Print why every file is compiled again
+    #[arg(long)]
+    explain: bool,
+    /// Record how long every package fetch, compile and link takes, and write a Chrome trace and a summary into .bld_cpp
+    #[arg(long)]
+    timings: bool,
 }
 
 #[derive(Subcommand, Debug)]
@@ -67,6 +92,7 @@ enum Commands {
         ///     - `default_compiler`: Sets the default compiler to use
         ///     - `default_language`: Sets the default language to use
         ///     - `license`: Sets the license to use. Give the path to the license file
+        ///     - `max_jobs`: Sets the number of jobs to run at once
         #[clap(verbatim_doc_comment)]
         parameter: String,
         /// Value to set the parameter to
@@ -74,6 +100,7 @@ enum Commands {
         ///     - `compiler`: `gcc`, `clang` Uses g++ or clang++ respectively
         ///     - `language`: `c`, `cpp`
         ///     - `license`: `path/to/license/file`
+        ///     - `max_jobs`: a number greater than 0
         #[clap(verbatim_doc_comment)]
         value: String,
     },
@@ -104,6 +131,34 @@ license = "NONE"
     let global_config = GlobalConfig::from_file(&config);
 
     let args = Args::parse();
+    if args.timings {
+        timings::enable();
+    }
+
+    //--jobs wins over the environment, which wins over the global config
+    let jobs = args.jobs.map(NonZeroUsize::get).or_else(|| {
+        let jobs = std::env::var("BUILDER_CPP_JOBS").ok()?;
+        match jobs.parse::<NonZeroUsize>() {
+            Ok(jobs) => Some(jobs.get()),
+            Err(_) => {
+                log(
+                    LogLevel::Error,
+                    &format!(
+                        "BUILDER_CPP_JOBS must be a number greater than 0, got: {}",
+                        jobs
+                    ),
+                );
+                std::process::exit(1);
+            }
+        }
+    });
+    let build_options = BuildOptions {
+        jobs: jobs.or(global_config.get_max_jobs()),
+        load_average: args.load_average,
+        keep_going: args.keep_going,
+        dry_run: args.dry_run,
+        explain: args.explain,
+    };
 
     if args.commands.is_some() {
         match args.commands {
@@ -153,10 +208,10 @@ license = "NONE"
         bin_flags::pre_gen_vsc();
     }
 
-    let (build_config, targets, packages) = bin_flags::parse_config();
+    let (build_config, targets, packages) = bin_flags::parse_config(&args.profile);
 
     if args.clean_packages {
-        bin_flags::clean_packages(&packages);
+        bin_flags::clean_packages(&build_config, &packages);
         std::process::exit(0);
     }
 
@@ -172,19 +227,26 @@ license = "NONE"
 
     if args.clean {
         log(LogLevel::Log, "Cleaning...");
-        bin_flags::clean(&targets);
+        bin_flags::clean(&build_config, &targets);
     }
 
     if args.build {
         log(LogLevel::Log, "Building...");
-        bin_flags::build(&build_config, &targets, gen_cc, gen_vsc, &packages);
+        bin_flags::build(
+            &build_config,
+            &targets,
+            gen_cc,
+            gen_vsc,
+            &packages,
+            &build_options,
+        );
     }
 
-    if args.run {
+    if let Some(run_target) = args.run {
         let bin_args: Vec<String> = args.bin_args;
 
         log(LogLevel::Log, "Running...");
-        let exe_target = targets.iter().find(|x| x.typ == "exe").unwrap();
+        let exe_target = bin_flags::select_exe_target(&targets, run_target.as_deref());
         let bin_args = if bin_args.is_empty() {
             None
         } else {
@@ -3,3 +3,5 @@
 pub mod configs;
 pub mod log;
 pub mod package;
+pub mod platform;
+pub mod schema;
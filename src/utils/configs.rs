@@ -1,39 +1,333 @@
+use glob::{MatchOptions, Pattern};
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     fs::File,
     io::Read,
     path::Path,
 };
-use toml::{Table, Value};
+use toml::Spanned;
 
 use super::log::{log, LogLevel};
+use super::platform;
+use super::schema::{closest_match, ConfigFile, DefineValue, Diagnostics, StrList, TargetTable};
+use crate::builder::cmds;
 
 //Toml utils
 /// Struct descibing the build config of the local project
 #[derive(Debug)]
 pub struct BuildConfig {
-    pub compiler: String,
+    pub cc: String,
+    pub cxx: String,
     pub packages: Vec<String>,
     pub cstandard: Option<String>,
     pub cppstandard: Option<String>,
     pub pre_build: Option<String>,
     pub post_build: Option<String>,
+    pub defines: Vec<Define>,
+    pub profiles: HashMap<String, Profile>,
+    pub profile: String,
+}
+
+impl BuildConfig {
+    /// Returns the compiler driver for a source file
+    /// # Arguments
+    /// * `is_c` - Whether the source file is a C file
+    pub fn compiler_for(&self, is_c: bool) -> &str {
+        if is_c {
+            &self.cc
+        } else {
+            &self.cxx
+        }
+    }
+
+    /// Returns true if a compiler takes gcc style flags like -std=
+    /// That is gcc, clang and the cc and c++ drivers, along with their cross
+    /// and versioned variants like x86_64-w64-mingw32-g++ or clang-17
+    /// # Arguments
+    /// * `compiler` - The compiler, possibly with a launcher like ccache and flags
+    pub fn is_gcc_like(compiler: &str) -> bool {
+        let args = cmds::split_args(compiler);
+        let Some(driver) = args.iter().rev().find(|arg| !arg.starts_with('-')) else {
+            return false;
+        };
+        let name = Path::new(driver)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let name = name.strip_suffix(".exe").unwrap_or(&name);
+        name == "cc" || name == "c++" || ["gcc", "g++", "clang"].iter().any(|f| name.contains(f))
+    }
+
+    /// Returns the C and C++ compilers that go with a single compiler name
+    /// gcc and g++ as well as clang and clang++ are paired up, keeping the dir,
+    /// the prefix and the suffix of the driver like in aarch64-linux-gnu-g++-13,
+    /// for any other compiler both languages use the same driver
+    fn compilers_from(compiler: &str) -> (String, String) {
+        let name = Path::new(compiler)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let dir = compiler.strip_suffix(name.as_str()).unwrap_or("");
+        //clang++ ends in g++ and starts with clang, so it is looked for first
+        for driver in ["clang++", "clang", "g++", "gcc"] {
+            let Some(start) = name.find(driver) else {
+                continue;
+            };
+            let (cc, cxx) = if driver.starts_with("clang") {
+                ("clang", "clang++")
+            } else {
+                ("gcc", "g++")
+            };
+            let prefix = &name[..start];
+            let suffix = &name[start + driver.len()..];
+            let with = |driver: &str| format!("{}{}{}{}", dir, prefix, driver, suffix);
+            return (with(cc), with(cxx));
+        }
+        (compiler.to_string(), compiler.to_string())
+    }
+
+    /// Returns the active profile
+    /// Falls back to an empty profile if this config does not define it
+    pub fn active_profile(&self) -> Profile {
+        self.profiles
+            .get(&self.profile)
+            .cloned()
+            .unwrap_or_else(|| Profile {
+                name: self.profile.clone(),
+                cflags: String::new(),
+                ldflags: String::new(),
+                defines: Vec::new(),
+            })
+    }
+}
+
+/// Struct describing a build profile like debug or release
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub name: String,
+    pub cflags: String,
+    pub ldflags: String,
+    pub defines: Vec<Define>,
+}
+
+impl Profile {
+    /// Returns the profiles every project has unless its config overrides them
+    fn builtin() -> HashMap<String, Profile> {
+        let mut profiles = HashMap::new();
+        profiles.insert(
+            "debug".to_string(),
+            Profile {
+                name: "debug".to_string(),
+                cflags: "-g".to_string(),
+                ldflags: String::new(),
+                defines: Vec::new(),
+            },
+        );
+        profiles.insert(
+            "release".to_string(),
+            Profile {
+                name: "release".to_string(),
+                cflags: "-O2".to_string(),
+                ldflags: String::new(),
+                defines: vec![Define {
+                    name: "NDEBUG".to_string(),
+                    value: None,
+                }],
+            },
+        );
+        profiles
+    }
+
+    /// Returns true if a name can be used for a profile
+    /// The name is used as a directory of .bld_cpp, so it can not be empty,
+    /// contain path separators or be a path of its own like . or ..
+    /// # Arguments
+    /// * `name` - The name of the profile
+    pub fn is_valid_name(name: &str) -> bool {
+        !name.is_empty() && name != "." && !name.contains(['/', '\\']) && !name.contains("..")
+    }
+}
+
+/// Struct describing a preprocessor define
+#[derive(Debug, Clone)]
+pub struct Define {
+    pub name: String,
+    pub value: Option<String>,
+}
+
+impl Define {
+    /// Returns the define as NAME=VALUE, or just NAME if it has no value
+    pub fn to_macro(&self) -> String {
+        match &self.value {
+            Some(value) => format!("{}={}", self.name, value),
+            None => self.name.clone(),
+        }
+    }
+
+    /// Returns the define as a compiler flag
+    pub fn to_flag(&self) -> String {
+        format!("-D{}", self.to_macro())
+    }
+
+    /// Merges lists of defines, a later define replaces an earlier one
+    /// with the same name
+    /// # Arguments
+    /// * `defines` - The defines in increasing order of precedence
+    pub fn merge(defines: Vec<Define>) -> Vec<Define> {
+        let mut merged: Vec<Define> = Vec::new();
+        for define in defines {
+            match merged.iter_mut().find(|x| x.name == define.name) {
+                Some(existing) => *existing = define,
+                None => merged.push(define),
+            }
+        }
+        merged
+    }
+
+    /// Converts a table of defines from the config into defines
+    /// Strings and numbers become the value of the define, true defines it
+    /// without a value and false leaves it out
+    /// # Arguments
+    /// * `defines` - The defines by name
+    fn from_table(defines: BTreeMap<String, DefineValue>) -> Vec<Define> {
+        let mut result = Vec::new();
+        for (name, value) in defines {
+            let value = match value {
+                DefineValue::Str(value) => Some(value),
+                DefineValue::Int(value) => Some(value.to_string()),
+                DefineValue::Float(value) => Some(value.to_string()),
+                DefineValue::Bool(true) => None,
+                DefineValue::Bool(false) => continue,
+            };
+            result.push(Define { name, value });
+        }
+        result
+    }
+}
+
+/// Returns true if the path is a file that can be compiled
+fn is_src_file(path: &str) -> bool {
+    path.ends_with(".cpp") || path.ends_with(".c") || path.ends_with(".cu")
 }
 
 /// Struct describing the target config of the local project
 #[derive(Debug, Clone)]
 pub struct TargetConfig {
     pub name: String,
-    pub src: String,
-    pub include_dir: String,
+    pub src: Vec<String>,
+    pub sources: Vec<String>,
+    pub exclude: Vec<String>,
+    pub include_dirs: Vec<String>,
+    pub public_include_dirs: Vec<String>,
     pub typ: String,
     pub cflags: String,
     pub libs: String,
     pub deps: Vec<String>,
+    pub cstandard: Option<String>,
+    pub cppstandard: Option<String>,
+    pub defines: Vec<Define>,
+    pub max_jobs: Option<usize>,
+    pub pkg_config: Vec<String>,
+    /// The name of the package the target comes from, None for the targets of the project
+    pub package: Option<String>,
 }
 
 impl TargetConfig {
-    /// Returns a vec of all filenames ending in .cpp or .c in the src directory
+    /// Returns the language standard a source file of this target is compiled with
+    /// The target standard takes precedence over the one in the build config
+    /// # Arguments
+    /// * `build_config` - The build config of the project
+    /// * `is_c` - Whether the source file is a C file
+    pub fn standard_for(&self, build_config: &BuildConfig, is_c: bool) -> String {
+        if is_c {
+            self.cstandard
+                .clone()
+                .or_else(|| build_config.cstandard.clone())
+                .unwrap_or_else(|| "c11".to_string())
+        } else {
+            self.cppstandard
+                .clone()
+                .or_else(|| build_config.cppstandard.clone())
+                .unwrap_or_else(|| "c++17".to_string())
+        }
+    }
+
+    /// Returns the paths of all source files of the target
+    /// Files found under the src dirs and files matching the sources patterns
+    /// are combined, then the ones matching an exclude pattern are dropped
+    /// # Arguments
+    /// * `warn_unmatched` - Warn about sources and exclude patterns that match nothing
+    pub fn get_src_files(&self, warn_unmatched: bool) -> Vec<String> {
+        let mut src_files = Vec::new();
+        for root in &self.src {
+            src_files.append(&mut TargetConfig::get_src_names(root));
+        }
+        for pattern in &self.sources {
+            let paths = glob::glob(pattern).unwrap_or_else(|e| {
+                log(
+                    LogLevel::Error,
+                    &format!(
+                        "Invalid sources pattern {} in target {}",
+                        pattern, self.name
+                    ),
+                );
+                log(LogLevel::Error, &format!("Error: {}", e));
+                std::process::exit(1);
+            });
+            let mut matched = paths
+                .filter_map(Result::ok)
+                .filter(|path| path.is_file())
+                .map(|path| path.to_str().unwrap().replace('\\', "/"))
+                .filter(|path| is_src_file(path))
+                .collect::<Vec<String>>();
+            if matched.is_empty() && warn_unmatched {
+                log(
+                    LogLevel::Warn,
+                    &format!(
+                        "sources pattern {} of target {} matches no source files",
+                        pattern, self.name
+                    ),
+                );
+            }
+            src_files.append(&mut matched);
+        }
+
+        //patterns match paths relative to the project root, with or without ./
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..Default::default()
+        };
+        for pattern in &self.exclude {
+            let exclude = Pattern::new(pattern.trim_start_matches("./")).unwrap_or_else(|e| {
+                log(
+                    LogLevel::Error,
+                    &format!(
+                        "Invalid exclude pattern {} in target {}",
+                        pattern, self.name
+                    ),
+                );
+                log(LogLevel::Error, &format!("Error: {}", e));
+                std::process::exit(1);
+            });
+            let num_files = src_files.len();
+            src_files.retain(|path| !exclude.matches_with(path.trim_start_matches("./"), options));
+            if src_files.len() == num_files && warn_unmatched {
+                log(
+                    LogLevel::Warn,
+                    &format!(
+                        "exclude pattern {} of target {} matches no source files",
+                        pattern, self.name
+                    ),
+                );
+            }
+        }
+
+        let mut seen = HashSet::new();
+        src_files.retain(|path| seen.insert(path.trim_start_matches("./").to_string()));
+        src_files
+    }
+
+    /// Returns a vec of the paths of all .c, .cpp and .cu files in the src directory
     /// # Arguments
     /// * `path` - The path to the src directory
     fn get_src_names(path: &str) -> Vec<String> {
@@ -50,12 +344,9 @@ impl TargetConfig {
             let entry = entry.unwrap();
             let path = entry.path();
             if path.is_file() {
-                let file_name = path.file_name().unwrap().to_str().unwrap();
-                if file_name.ends_with(".cpp")
-                    || file_name.ends_with(".c")
-                    || file_name.ends_with(".cu")
-                {
-                    src_names.push(file_name.to_string());
+                let path = path.to_str().unwrap().replace('\\', "/");
+                if is_src_file(&path) {
+                    src_names.push(path);
                 }
             } else if path.is_dir() {
                 let dir_name = path.to_str().unwrap().replace('\\', "/");
@@ -66,13 +357,19 @@ impl TargetConfig {
         src_names
     }
 
-    fn arrange_targets(targets: Vec<TargetConfig>) -> Vec<TargetConfig> {
+    //orders the targets so every target comes after its deps
+    //exits with the full path if the deps form a cycle
+    //a cycle can not leave the config file, the targets of a package only
+    //depend on targets of the same package, and packages that use each other
+    //are found when they are parsed
+    fn arrange_targets(targets: Vec<TargetConfig>, path: &str) -> Vec<TargetConfig> {
         let mut proj_indices: HashMap<&String, usize> = HashMap::new();
         for (i, proj) in targets.iter().enumerate() {
             proj_indices.insert(&proj.name, i);
         }
 
         let mut visited: HashSet<usize> = HashSet::new();
+        let mut stack: Vec<usize> = Vec::new();
 
         let mut result: Vec<TargetConfig> = Vec::new();
 
@@ -81,31 +378,153 @@ impl TargetConfig {
             projects: &Vec<TargetConfig>,
             proj_indices: &HashMap<&String, usize>,
             visited: &mut HashSet<usize>,
+            stack: &mut Vec<usize>,
             result: &mut Vec<TargetConfig>,
-        ) {
+        ) -> Result<(), Vec<String>> {
+            //a target that is still on the stack depends on itself
+            if let Some(start) = stack.iter().position(|&i| i == proj_index) {
+                let mut cycle = stack[start..]
+                    .iter()
+                    .map(|&i| projects[i].name.clone())
+                    .collect::<Vec<String>>();
+                cycle.push(projects[proj_index].name.clone());
+                return Err(cycle);
+            }
             if visited.contains(&proj_index) {
-                return;
+                return Ok(());
             }
-            visited.insert(proj_index);
+            stack.push(proj_index);
             for dep_name in &projects[proj_index].deps {
                 if let Some(&dep_index) = proj_indices.get(dep_name) {
-                    dfs(dep_index, projects, proj_indices, visited, result);
+                    dfs(dep_index, projects, proj_indices, visited, stack, result)?;
                 }
             }
+            stack.pop();
+            visited.insert(proj_index);
             result.push(projects[proj_index].clone());
+            Ok(())
         }
         for (i, _) in targets.iter().enumerate() {
-            dfs(i, &targets, &proj_indices, &mut visited, &mut result);
+            let arranged = dfs(
+                i,
+                &targets,
+                &proj_indices,
+                &mut visited,
+                &mut stack,
+                &mut result,
+            );
+            if let Err(cycle) = arranged {
+                log(
+                    LogLevel::Error,
+                    &format!("Dependency cycle found in {}", path),
+                );
+                log(LogLevel::Error, &format!("  {}", cycle.join(" -> ")));
+                std::process::exit(1);
+            }
         }
         result
     }
+
+    /// Checks that every dep of the targets names one of the targets or a
+    /// target provided by a package, suggesting the closest name if not
+    /// # Arguments
+    /// * `targets` - The targets of a config file
+    /// * `package_targets` - The names of the targets the packages provide
+    /// * `path` - The path to the config file, used in messages
+    pub fn check_deps(targets: &[TargetConfig], package_targets: &[String], path: &str) {
+        let names = targets
+            .iter()
+            .map(|target| target.name.as_str())
+            .chain(package_targets.iter().map(String::as_str))
+            .collect::<Vec<&str>>();
+        let mut found_unknown = false;
+        for target in targets {
+            //init used to write deps = [""], an empty name is no dep
+            for dep in target.deps.iter().filter(|dep| !dep.is_empty()) {
+                if names.contains(&dep.as_str()) {
+                    continue;
+                }
+                found_unknown = true;
+                let mut message = format!(
+                    "Target {} in {} depends on unknown target {}",
+                    target.name, path, dep
+                );
+                if let Some(suggestion) = closest_match(dep, &names) {
+                    message.push_str(&format!(", did you mean {}?", suggestion));
+                }
+                log(LogLevel::Error, &message);
+            }
+        }
+        if found_unknown {
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Returns the config file of the project in a directory
+/// builder_cpp.toml is preferred, the older per platform config_linux.toml
+/// and config_win32.toml are used if it does not exist
+/// # Arguments
+/// * `dir` - The directory of the project
+pub fn config_path(dir: &str) -> String {
+    let dir = dir.trim_end_matches('/');
+    let unified = format!("{}/builder_cpp.toml", dir);
+    if Path::new(&unified).exists() {
+        return unified;
+    }
+    #[cfg(target_os = "windows")]
+    return format!("{}/config_win32.toml", dir);
+    #[cfg(not(target_os = "windows"))]
+    return format!("{}/config_linux.toml", dir);
+}
+
+//applies the [target.'cfg(...)'.<target name>] sections that match the
+//current platform to the targets of the config
+//lists and tables are extended, flags and libs are appended and any other
+//key is replaced
+fn apply_platform_overrides(
+    overrides: BTreeMap<String, BTreeMap<String, Spanned<TargetTable>>>,
+    targets: &mut [Spanned<TargetTable>],
+    diags: &mut Diagnostics,
+) {
+    for (cfg, sections) in overrides {
+        let matches = match platform::cfg_matches(&cfg) {
+            Ok(matches) => matches,
+            Err(why) => {
+                //the cfg is written in the header of each of its sections
+                let span = sections.values().next().map(Spanned::span);
+                diags.error(
+                    span.unwrap_or(0..0),
+                    &format!("target.'{}'", cfg),
+                    &format!("invalid cfg: {}", why),
+                );
+                continue;
+            }
+        };
+        if !matches {
+            continue;
+        }
+        for (name, section) in sections {
+            let path = format!("target.'{}'.{}", cfg, name);
+            let target = targets
+                .iter_mut()
+                .find(|target| target.get_ref().name.as_ref().map(|x| x.get_ref()) == Some(&name));
+            match target {
+                Some(target) => target.get_mut().apply(section.into_inner(), &path, diags),
+                None => diags.error(section.span(), &path, "names an unknown target"),
+            }
+        }
+    }
 }
 
 /// This function is used to parse the config file of local project
+/// A value of the wrong type is reported on its own, every other problem in
+/// the file is reported at once, all along with their line and column, before
+/// exiting
 /// # Arguments
 /// * `path` - The path to the config file
-/// * `check_dup_src` - If true, the function will check for duplicately named source files
-pub fn parse_config(path: &str, check_dup_src: bool) -> (BuildConfig, Vec<TargetConfig>) {
+/// * `check_srcs` - If true, the function will check that every target has source files
+pub fn parse_config(path: &str, check_srcs: bool) -> (BuildConfig, Vec<TargetConfig>) {
     //open toml file and parse it into a string
     let mut file = File::open(path).unwrap_or_else(|_| {
         log(
@@ -122,235 +541,258 @@ pub fn parse_config(path: &str, check_dup_src: bool) -> (BuildConfig, Vec<Target
         );
         std::process::exit(1);
     });
-    let config = contents.parse::<Table>().unwrap_or_else(|e| {
-        log(
-            LogLevel::Error,
-            &format!("Could not parse config file: {}", path),
-        );
-        log(LogLevel::Error, &format!("Error: {}", e));
-        std::process::exit(1);
-    });
-    let compiler = config["build"]["compiler"]
-        .as_str()
-        .unwrap_or_else(|| {
-            log(LogLevel::Error, "Could not find compiler in config file");
-            std::process::exit(1);
-        })
-        .to_string();
-
-    let mut pkgs: Vec<String> = Vec::new();
-    let empty_value = Value::Array(Vec::new());
-    //pkgs is optional
-    let pkgs_toml = config["build"]
-        .as_table()
-        .unwrap_or_else(|| {
-            log(LogLevel::Error, "Could not find build in config file");
-            std::process::exit(1);
-        })
-        .get("packages")
-        .unwrap_or(&empty_value)
-        .as_array()
-        .unwrap_or_else(|| {
-            log(LogLevel::Error, "packages is not an array");
-            std::process::exit(1);
-        });
-
-    for pkg in pkgs_toml {
-        pkgs.push(
-            pkg.as_str()
-                .unwrap_or_else(|| {
-                    log(LogLevel::Error, "packages are a vec of strings");
-                    std::process::exit(1);
-                })
-                .to_string(),
-        );
-    }
 
-    let cstandard = config["build"].get("cstandard").map(|x| {
-        x.as_str()
-            .unwrap_or_else(|| {
-                log(
-                    LogLevel::Error,
-                    "cstandard is a string containing the c standard to use",
-                );
-                std::process::exit(1);
-            })
-            .to_string()
-    });
+    let mut diags = Diagnostics::new(path, &contents);
+    let Some(config) = ConfigFile::parse(&mut diags) else {
+        //the error has been recorded, finish reports it and exits
+        diags.finish();
+        std::process::exit(1);
+    };
 
-    let cppstandard = config["build"].get("cppstandard").map(|x| {
-        x.as_str()
-            .unwrap_or_else(|| {
-                log(
-                    LogLevel::Error,
-                    "cppstandard is a string containing the cpp standard to use",
-                );
-                std::process::exit(1);
-            })
-            .to_string()
-    });
+    let (build_span, build) = match config.build {
+        Some(build) => (build.span(), build.into_inner()),
+        None => {
+            diags.error(0..0, "build", "Could not find build in config file");
+            (0..0, Default::default())
+        }
+    };
 
-    let pre_build = config["build"].get("pre_build").map(|x| {
-        x.as_str()
-            .unwrap_or_else(|| {
-                log(
-                    LogLevel::Error,
-                    "pre_build is a string containing the command to run",
-                );
-                std::process::exit(1);
-            })
-            .to_string()
-    });
+    //cc and cxx pick the compilers for C and C++ files
+    //compiler is the older single key, the other language is derived from it
+    let (cc, cxx) = match (build.cc, build.cxx, build.compiler) {
+        (Some(cc), Some(cxx), _) => (cc, cxx),
+        //the missing one is of the same family as compiler, or else as the other one
+        (Some(cc), None, compiler) => {
+            let family = compiler.unwrap_or(cc.clone());
+            (cc, BuildConfig::compilers_from(&family).1)
+        }
+        (None, Some(cxx), compiler) => {
+            let family = compiler.unwrap_or(cxx.clone());
+            (BuildConfig::compilers_from(&family).0, cxx)
+        }
+        (None, None, Some(compiler)) => BuildConfig::compilers_from(&compiler),
+        (None, None, None) => {
+            diags.error(
+                build_span,
+                "build",
+                "Could not find compiler, set cc and cxx, or compiler",
+            );
+            (String::new(), String::new())
+        }
+    };
 
-    let post_build = config["build"].get("post_build").map(|x| {
-        x.as_str()
-            .unwrap_or_else(|| {
-                log(
-                    LogLevel::Error,
-                    "post_build is a string containing the command to run",
-                );
-                std::process::exit(1);
-            })
-            .to_string()
-    });
+    //profiles are optional, the builtin debug and release ones are replaced
+    //if the config defines them
+    let mut profiles = Profile::builtin();
+    for (name, profile) in config.profile {
+        if !Profile::is_valid_name(&name) {
+            diags.error(
+                profile.span(),
+                &format!("profile.{}", name),
+                "profile names can not be empty or contain path separators or ..",
+            );
+            continue;
+        }
+        let profile = profile.into_inner();
+        profiles.insert(
+            name.clone(),
+            Profile {
+                name,
+                cflags: profile.cflags,
+                ldflags: profile.ldflags,
+                defines: Define::from_table(profile.defines),
+            },
+        );
+    }
 
     //parse the string into a struct
     let build_config = BuildConfig {
-        compiler,
-        cstandard,
-        cppstandard,
-        packages: pkgs,
-        pre_build,
-        post_build,
+        cc,
+        cxx,
+        cstandard: build.cstandard,
+        cppstandard: build.cppstandard,
+        packages: build.packages,
+        pre_build: build.pre_build,
+        post_build: build.post_build,
+        defines: Define::from_table(build.defines),
+        profiles,
+        profile: "debug".to_string(),
     };
 
+    let mut targets = match config.targets {
+        Some(targets) => targets,
+        None => {
+            diags.error(0..0, "targets", "Could not find targets in config file");
+            Vec::new()
+        }
+    };
+    apply_platform_overrides(config.target, &mut targets, &mut diags);
+
     let mut tgt = Vec::new();
-    let targets = config["targets"].as_array().unwrap_or_else(|| {
-        log(LogLevel::Error, "Could not find targets in config file");
-        std::process::exit(1);
-    });
+    let mut name_spans = Vec::new();
+    for (i, target) in targets.into_iter().enumerate() {
+        let path = format!("targets[{}]", i);
+        let span = target.span();
+        let target = target.into_inner();
+        let mut require = |value: Option<Spanned<String>>, key: &str| match value {
+            Some(value) => (value.span(), value.into_inner()),
+            None => {
+                diags.error(
+                    span.clone(),
+                    &path,
+                    &format!("missing required key {}", key),
+                );
+                (span.clone(), String::new())
+            }
+        };
+        let (name_span, name) = require(target.name, "name");
+        let (type_span, typ) = require(target.typ, "type");
+        //interface targets often pass on no flags or libs at all
+        let mut flags = |value: Option<String>, key: &str| match value {
+            Some(value) => value,
+            None if typ == "interface" => String::new(),
+            None => {
+                diags.error(
+                    span.clone(),
+                    &path,
+                    &format!("missing required key {}", key),
+                );
+                String::new()
+            }
+        };
+        let cflags = flags(target.cflags, "cflags");
+        let libs = flags(target.libs, "libs");
+        let list = |value: Option<StrList>| value.map(StrList::into_vec).unwrap_or_default();
 
-    for target in targets {
-        let mut deps: Vec<String> = Vec::new();
-        let empty_value = Value::Array(Vec::new());
-        //deps is optional
-        let deps_toml = target
-            .get("deps")
-            .unwrap_or(&empty_value)
-            .as_array()
-            .unwrap_or_else(|| {
-                log(LogLevel::Error, "Deps is not an array");
-                std::process::exit(1);
-            });
-        for dep in deps_toml {
-            deps.push(
-                dep.as_str()
-                    .unwrap_or_else(|| {
-                        log(LogLevel::Error, "Deps are a vec of strings");
-                        std::process::exit(1);
-                    })
-                    .to_string(),
+        //interface targets are header only and need no src
+        if target.src.is_none() && target.sources.is_none() && typ != "interface" {
+            diags.error(
+                span.clone(),
+                &format!("{}.src", path),
+                &format!("Could not find src or sources for target: {}", name),
+            );
+        }
+        if let Some(max_jobs) = target.max_jobs.as_ref().filter(|x| *x.get_ref() == 0) {
+            diags.error(
+                max_jobs.span(),
+                &format!("{}.max_jobs", path),
+                "max_jobs must be at least 1",
             );
         }
 
-        let target_config = TargetConfig {
-            name: target["name"]
-                .as_str()
-                .unwrap_or_else(|| {
-                    log(LogLevel::Error, "Could not find name in config file");
-                    std::process::exit(1);
-                })
-                .to_string(),
-            src: target["src"]
-                .as_str()
-                .unwrap_or_else(|| {
-                    log(LogLevel::Error, "Could not find src in config file");
-                    std::process::exit(1);
-                })
-                .to_string(),
-            include_dir: target["include_dir"]
-                .as_str()
-                .unwrap_or_else(|| {
-                    log(LogLevel::Error, "Could not find include_dir in config file");
-                    std::process::exit(1);
-                })
-                .to_string(),
-            typ: target["type"]
-                .as_str()
-                .unwrap_or_else(|| {
-                    log(LogLevel::Error, "Could not find type in config file");
-                    std::process::exit(1);
-                })
-                .to_string(),
-            cflags: target["cflags"]
-                .as_str()
-                .unwrap_or_else(|| {
-                    log(LogLevel::Error, "Could not find cflags in config file");
-                    std::process::exit(1);
-                })
-                .to_string(),
-            libs: target["libs"]
-                .as_str()
-                .unwrap_or_else(|| {
-                    log(LogLevel::Error, "Could not find libs in config file");
-                    std::process::exit(1);
-                })
-                .to_string(),
-            deps,
+        let mut target_config = TargetConfig {
+            name,
+            src: list(target.src),
+            sources: list(target.sources),
+            exclude: list(target.exclude),
+            //include_dirs are private to the target, public_include_dirs are
+            //exported to its dependants. The older include_dir is public
+            include_dirs: list(target.include_dirs),
+            public_include_dirs: list(target.include_dir)
+                .into_iter()
+                .chain(list(target.public_include_dirs))
+                .collect(),
+            typ,
+            cflags,
+            libs,
+            deps: target.deps.unwrap_or_default(),
+            cstandard: target.cstandard,
+            cppstandard: target.cppstandard,
+            defines: Define::from_table(target.defines.unwrap_or_default()),
+            max_jobs: target.max_jobs.map(Spanned::into_inner),
+            pkg_config: list(target.pkg_config),
+            package: None,
         };
-        if target_config.typ != "exe" && target_config.typ != "dll" {
-            log(LogLevel::Error, "Type must be exe or dll");
-            std::process::exit(1);
+        //lib is accepted as an alias for static
+        if target_config.typ == "lib" {
+            target_config.typ = "static".to_string();
+        }
+        if target_config.typ != "exe"
+            && target_config.typ != "dll"
+            && target_config.typ != "static"
+            && target_config.typ != "interface"
+        {
+            diags.error(
+                type_span,
+                &format!("{}.type", path),
+                "Type must be exe, dll, static or interface",
+            );
         }
+        name_spans.push(name_span);
         tgt.push(target_config);
     }
 
     if tgt.is_empty() {
-        log(LogLevel::Error, "No targets found");
-        std::process::exit(1);
+        diags.error(0..0, "targets", "No targets found");
     }
     //Check for duplicate target names
-    for i in 0..tgt.len() - 1 {
+    for i in 0..tgt.len() {
         for j in i + 1..tgt.len() {
             if tgt[i].name == tgt[j].name {
-                log(
-                    LogLevel::Error,
+                diags.error(
+                    name_spans[j].clone(),
+                    &format!("targets[{}].name", j),
                     &format!("Duplicate target names found: {}", tgt[i].name),
                 );
-                std::process::exit(1);
             }
         }
     }
+    diags.finish();
 
-    if check_dup_src {
-        for target in &tgt {
-            let mut src_file_names = TargetConfig::get_src_names(&target.src);
-            src_file_names.sort();
-            if src_file_names.is_empty() {
+    if check_srcs {
+        for target in tgt.iter().filter(|x| x.typ != "interface") {
+            if target.get_src_files(true).is_empty() {
                 log(
                     LogLevel::Error,
                     &format!("No source files found for target: {}", target.name),
                 );
                 std::process::exit(1);
             }
-            for i in 0..src_file_names.len() - 1 {
-                if src_file_names[i] == src_file_names[i + 1] {
-                    log(
-                        LogLevel::Error,
-                        &format!("Duplicate source files found for target: {}", target.name),
-                    );
-                    log(LogLevel::Error, "Source files must be unique");
-                    log(
-                        LogLevel::Error,
-                        &format!("Duplicate file: {}", src_file_names[i]),
-                    );
-                    std::process::exit(1);
-                }
-            }
         }
     }
 
-    let tgt_arranged = TargetConfig::arrange_targets(tgt);
+    let tgt_arranged = TargetConfig::arrange_targets(tgt, path);
 
     (build_config, tgt_arranged)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pairs_compilers_of_the_same_family() {
+        let cases = [
+            ("gcc", "gcc", "g++"),
+            ("g++", "gcc", "g++"),
+            ("clang", "clang", "clang++"),
+            ("clang++", "clang", "clang++"),
+            (
+                "x86_64-w64-mingw32-g++",
+                "x86_64-w64-mingw32-gcc",
+                "x86_64-w64-mingw32-g++",
+            ),
+            ("clang++-17", "clang-17", "clang++-17"),
+            (
+                "aarch64-linux-gnu-gcc-13",
+                "aarch64-linux-gnu-gcc-13",
+                "aarch64-linux-gnu-g++-13",
+            ),
+            ("/usr/bin/clang++", "/usr/bin/clang", "/usr/bin/clang++"),
+            (
+                "C:/mingw/bin/g++.exe",
+                "C:/mingw/bin/gcc.exe",
+                "C:/mingw/bin/g++.exe",
+            ),
+            ("icx", "icx", "icx"),
+        ];
+        for (compiler, cc, cxx) in cases {
+            assert_eq!(
+                BuildConfig::compilers_from(compiler),
+                (cc.to_string(), cxx.to_string()),
+                "{}",
+                compiler
+            );
+        }
+    }
+}
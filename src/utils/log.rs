@@ -17,6 +17,8 @@ pub enum LogLevel {
 /// * `message` - The message to log
 /// # Example
 /// ```
+/// # use builder_cpp::utils::log::{log, LogLevel};
+/// # let error = "oops";
 /// log(LogLevel::Info, "Hello World!");
 /// log(LogLevel::Error, &format!("Something went wrong! {}", error));
 /// ```
@@ -29,6 +31,7 @@ pub enum LogLevel {
 /// * `Log`
 /// * `Warn`
 /// * `Error`
+///
 /// If the environment variable is not set, the default log level is `Log`
 pub fn log(level: LogLevel, message: &str) {
     let level_str = match level {
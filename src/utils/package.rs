@@ -1,7 +1,8 @@
-use std::{path::Path, process::Command};
+use std::{collections::HashMap, path::Path, process::Command};
 
-use super::configs::{parse_config, BuildConfig, TargetConfig};
+use super::configs::{config_path, parse_config, BuildConfig, TargetConfig};
 use super::log::{log, LogLevel};
+use crate::builder::timings;
 
 #[derive(Debug)]
 /// Represents a package
@@ -31,17 +32,6 @@ impl Package {
         }
     }
 
-    pub fn obj_dir() -> &'static str {
-        #[cfg(target_os = "linux")]
-        let obj_dir = "./.bld_cpp/obj_linux";
-        #[cfg(target_os = "android")]
-        let obj_dir = "./.bld_cpp/obj_android";
-        #[cfg(target_os = "windows")]
-        let obj_dir = "./.bld_cpp/obj_win32";
-
-        obj_dir
-    }
-
     /// Updates the package to latest commit
     pub fn update(&self) {
         let mut cmd = String::from("cd");
@@ -127,29 +117,42 @@ impl Package {
         }
     }
 
-    /// Parses a package contained in a folder
-    /// The folder must contain a config timl file
+    /// Parses the packages a config uses
+    /// Each package folder must contain a config toml file
     /// # Arguments
-    /// * `path` - The path to the folder containing the package
-    pub fn parse_packages(path: &str) -> Vec<Package> {
+    /// * `root_config` - The build config listing the packages
+    /// * `profile` - The profile the packages are built with
+    pub fn parse_packages(root_config: &BuildConfig, profile: &str) -> Vec<Package> {
+        Self::parse_packages_within(root_config, profile, &[])
+    }
+
+    //parses the packages of a config that is itself a package of the packages
+    //in chain, exits with the full path if a package ends up using itself
+    fn parse_packages_within(
+        root_config: &BuildConfig,
+        profile: &str,
+        chain: &[String],
+    ) -> Vec<Package> {
         let mut packages: Vec<Package> = Vec::new();
         //initialize fields
         let mut name = String::new();
         let mut repo = String::new();
         let mut branch = String::new();
         let mut build_config = BuildConfig {
-            compiler: String::new(),
+            cc: String::new(),
+            cxx: String::new(),
             packages: Vec::new(),
             cstandard: Some("c11".to_string()),
             cppstandard: Some("c++17".to_string()),
             pre_build: None,
             post_build: None,
+            defines: Vec::new(),
+            profiles: HashMap::new(),
+            profile: profile.to_string(),
         };
         let mut target_configs = Vec::new();
 
-        //parse the root toml file
-        let (build_config_toml, _) = parse_config(path, false);
-        for package in build_config_toml.packages {
+        for package in &root_config.packages {
             let deets = package.split_whitespace().collect::<Vec<&str>>();
             if deets.len() != 2 {
                 log(
@@ -162,6 +165,13 @@ impl Package {
             branch = deets[1].to_string();
 
             name = repo.split('/').collect::<Vec<&str>>()[1].to_string();
+            if let Some(start) = chain.iter().position(|used| *used == name) {
+                let mut cycle = chain[start..].to_vec();
+                cycle.push(name.clone());
+                log(LogLevel::Error, "Package cycle found");
+                log(LogLevel::Error, &format!("  {}", cycle.join(" -> ")));
+                std::process::exit(1);
+            }
             let source_dir = format!("./.bld_cpp/sources/{}/", name);
             if !Path::new(&source_dir).exists() {
                 Command::new("mkdir")
@@ -186,7 +196,8 @@ impl Package {
                     .arg(&branch)
                     .arg(&repo_https)
                     .arg(&source_dir);
-                let output = cmd.output().expect("Failed to execute git clone");
+                let output = timings::time("package", &repo, || cmd.output())
+                    .expect("Failed to execute git clone");
                 if !output.status.success() {
                     log(
                         LogLevel::Error,
@@ -198,85 +209,81 @@ impl Package {
                     std::process::exit(1);
                 }
             }
-            #[cfg(target_os = "linux")]
-            let pkg_toml = format!("{}/config_linux.toml", source_dir).replace("//", "/");
-            #[cfg(target_os = "android")]
-            let pkg_toml = format!("{}/config_linux.toml", source_dir).replace("//", "/");
-            #[cfg(target_os = "windows")]
-            let pkg_toml = format!("{}/config_win32.toml", source_dir).replace("//", "/");
+            let pkg_toml = config_path(&source_dir).replace("//", "/");
 
             let (pkg_bld_config_toml, pkg_targets_toml) = parse_config(&pkg_toml, false);
             log(LogLevel::Info, &format!("Parsed {}", pkg_toml));
 
             if !pkg_bld_config_toml.packages.is_empty() {
-                for foreign_package in Package::parse_packages(&pkg_toml) {
-                    packages.push(foreign_package);
-                }
+                let mut chain = chain.to_vec();
+                chain.push(name.clone());
+                let foreign_packages =
+                    Package::parse_packages_within(&pkg_bld_config_toml, profile, &chain);
+                check_foreign_deps(&pkg_targets_toml, &foreign_packages, &pkg_toml);
+                packages.extend(foreign_packages);
             }
+            //the targets of a package are linked with no other package, so
+            //their deps can only name the targets of the same package
+            TargetConfig::check_deps(&pkg_targets_toml, &[], &pkg_toml);
 
             build_config = pkg_bld_config_toml;
-            build_config.compiler = build_config_toml.compiler.clone();
-            if !Path::new(Package::obj_dir()).exists() {
-                let cmd = Command::new("mkdir")
-                    .arg("-p")
-                    .arg(Package::obj_dir())
-                    .output();
-                if cmd.is_err() {
-                    log(
-                        LogLevel::Error,
-                        &format!("Failed to create {}", Package::obj_dir()),
-                    );
-                    std::process::exit(1);
-                }
-                log(LogLevel::Info, &format!("Created {}", Package::obj_dir()));
-            }
+            build_config.cc = root_config.cc.clone();
+            build_config.cxx = root_config.cxx.clone();
+            build_config.profile = profile.to_string();
 
+            //headers are copied only once, when the package is first seen
+            let includes_existed = Path::new(&format!("./.bld_cpp/includes/{}", name)).exists();
             let tgt_configs = pkg_targets_toml;
             for mut tgt in tgt_configs {
-                if tgt.typ != "dll" {
+                if tgt.typ == "exe" {
                     continue;
                 }
-                tgt.src = format!("{}/{}", source_dir, tgt.src)
+                //src dirs and patterns are relative to the package root
+                let in_source_dir = |path: &String| {
+                    format!("{}/{}", source_dir, path)
+                        .replace('\\', "/")
+                        .replace("/./", "/")
+                        .replace("//", "/")
+                };
+                tgt.src = tgt.src.iter().map(in_source_dir).collect();
+                tgt.sources = tgt.sources.iter().map(in_source_dir).collect();
+                tgt.exclude = tgt.exclude.iter().map(in_source_dir).collect();
+                tgt.include_dirs = tgt.include_dirs.iter().map(in_source_dir).collect();
+                //public headers are copied into one include dir per package
+                let pkg_inc_dir = format!("./.bld_cpp/includes/{}", name)
                     .replace('\\', "/")
                     .replace("/./", "/")
                     .replace("//", "/");
-                let old_inc_dir = tgt.include_dir.clone();
-                tgt.include_dir = format!("./.bld_cpp/includes/{}", name)
-                    .replace('\\', "/")
-                    .replace("/./", "/")
-                    .replace("//", "/");
-                if !Path::new(&tgt.include_dir).exists() {
-                    let cmd = Command::new("mkdir")
-                        .arg("-p")
-                        .arg(&tgt.include_dir)
-                        .output();
-                    if cmd.is_err() {
-                        log(
-                            LogLevel::Error,
-                            &format!("Failed to create {}", tgt.include_dir),
-                        );
-                        std::process::exit(1);
+                if !includes_existed {
+                    if !Path::new(&pkg_inc_dir).exists() {
+                        let cmd = Command::new("mkdir").arg("-p").arg(&pkg_inc_dir).output();
+                        if cmd.is_err() {
+                            log(
+                                LogLevel::Error,
+                                &format!("Failed to create {}", pkg_inc_dir),
+                            );
+                            std::process::exit(1);
+                        }
+                        log(LogLevel::Info, &format!("Created {}", pkg_inc_dir));
                     }
-                    log(LogLevel::Info, &format!("Created {}", tgt.include_dir));
-                    let mut cm = String::new();
-                    cm.push_str("cp -r ");
-                    cm.push_str(
-                        &format!("{}/{}/* ", source_dir, old_inc_dir)
-                            .replace('\\', "/")
-                            .replace("/./", "/")
-                            .replace("//", "/"),
-                    );
-                    cm.push_str(&tgt.include_dir);
-                    cm.push_str("/ ");
-                    let cmd = Command::new("sh").arg("-c").arg(&cm).output();
-                    if cmd.is_err() {
-                        log(
-                            LogLevel::Error,
-                            &format!("Failed to create {}", tgt.include_dir),
-                        );
-                        std::process::exit(1);
+                    for old_inc_dir in &tgt.public_include_dirs {
+                        let mut cm = String::new();
+                        cm.push_str("cp -r ");
+                        cm.push_str(&format!("{}/* ", in_source_dir(old_inc_dir)));
+                        cm.push_str(&pkg_inc_dir);
+                        cm.push_str("/ ");
+                        let cmd = Command::new("sh").arg("-c").arg(&cm).output();
+                        if cmd.is_err() {
+                            log(
+                                LogLevel::Error,
+                                &format!("Failed to create {}", pkg_inc_dir),
+                            );
+                            std::process::exit(1);
+                        }
                     }
                 }
+                tgt.public_include_dirs = vec![pkg_inc_dir];
+                tgt.package = Some(name.clone());
                 target_configs.push(tgt);
             }
         }
@@ -293,3 +300,32 @@ impl Package {
         packages
     }
 }
+
+//exits if a target of a package depends on a target of one of the packages
+//the package uses, those are linked into the project but not into the package
+fn check_foreign_deps(targets: &[TargetConfig], foreign_packages: &[Package], path: &str) {
+    let mut found_foreign = false;
+    for target in targets {
+        for dep in &target.deps {
+            let foreign = foreign_packages.iter().find(|package| {
+                package
+                    .target_configs
+                    .iter()
+                    .any(|foreign| foreign.name == *dep)
+            });
+            if let Some(package) = foreign {
+                found_foreign = true;
+                log(
+                    LogLevel::Error,
+                    &format!(
+                        "Target {} in {} depends on {} of package {}, the targets of a package can only depend on the targets of the same package",
+                        target.name, path, dep, package.name
+                    ),
+                );
+            }
+        }
+    }
+    if found_foreign {
+        std::process::exit(1);
+    }
+}
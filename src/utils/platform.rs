@@ -0,0 +1,212 @@
+//Evaluates the cfg expressions of platform specific config sections
+//like [target.'cfg(linux)'.main] or [target.'cfg(not(windows))'.main]
+
+/// Returns the name of the os builder_cpp was built for
+pub fn current_os() -> &'static str {
+    std::env::consts::OS
+}
+
+/// Returns the family of the os builder_cpp was built for, unix or windows
+pub fn current_family() -> &'static str {
+    std::env::consts::FAMILY
+}
+
+/// Evaluates a cfg expression like `cfg(linux)` against the current platform
+/// Supports the os names, `unix`, `windows`, `target_os = "..."`,
+/// `target_family = "..."` and the `all`, `any` and `not` combinators
+/// # Arguments
+/// * `expr` - The cfg expression, including the surrounding cfg()
+pub fn cfg_matches(expr: &str) -> Result<bool, String> {
+    let inner = expr
+        .trim()
+        .strip_prefix("cfg(")
+        .and_then(|x| x.strip_suffix(')'))
+        .ok_or_else(|| format!("{} is not of the form cfg(...)", expr))?;
+    let tokens = tokenize(inner)?;
+    let mut pos = 0;
+    let result = eval(&tokens, &mut pos)?;
+    if pos != tokens.len() {
+        return Err(format!("Unexpected trailing tokens in {}", expr));
+    }
+    Ok(result)
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Eq,
+    Comma,
+    Open,
+    Close,
+}
+
+//splits a cfg expression into tokens
+fn tokenize(expr: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = expr.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            ' ' | '\t' => {
+                chars.next();
+            }
+            '=' => {
+                chars.next();
+                tokens.push(Token::Eq);
+            }
+            ',' => {
+                chars.next();
+                tokens.push(Token::Comma);
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            '"' => {
+                chars.next();
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => value.push(c),
+                        None => return Err(format!("Unterminated string in cfg({})", expr)),
+                    }
+                }
+                tokens.push(Token::Str(value));
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                let mut ident = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_alphanumeric() || c == '_' {
+                        ident.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(Token::Ident(ident));
+            }
+            _ => return Err(format!("Unexpected character {} in cfg({})", c, expr)),
+        }
+    }
+    Ok(tokens)
+}
+
+//evaluates a single predicate starting at pos
+fn eval(tokens: &[Token], pos: &mut usize) -> Result<bool, String> {
+    let ident = match tokens.get(*pos) {
+        Some(Token::Ident(ident)) => ident.clone(),
+        _ => return Err("Expected a cfg predicate".to_string()),
+    };
+    *pos += 1;
+
+    match tokens.get(*pos) {
+        Some(Token::Open) => {
+            *pos += 1;
+            let mut values = Vec::new();
+            while tokens.get(*pos) != Some(&Token::Close) {
+                values.push(eval(tokens, pos)?);
+                match tokens.get(*pos) {
+                    Some(Token::Comma) => *pos += 1,
+                    Some(Token::Close) => {}
+                    _ => return Err(format!("Expected , or ) in {}(...)", ident)),
+                }
+            }
+            *pos += 1;
+            match ident.as_str() {
+                "all" => Ok(values.iter().all(|x| *x)),
+                "any" => Ok(values.iter().any(|x| *x)),
+                "not" if values.len() == 1 => Ok(!values[0]),
+                "not" => Err("not(...) takes exactly one predicate".to_string()),
+                _ => Err(format!("Unknown cfg function {}", ident)),
+            }
+        }
+        Some(Token::Eq) => {
+            *pos += 1;
+            let value = match tokens.get(*pos) {
+                Some(Token::Str(value)) => value.clone(),
+                _ => return Err(format!("Expected a string after {} =", ident)),
+            };
+            *pos += 1;
+            match ident.as_str() {
+                "target_os" => Ok(value == current_os()),
+                "target_family" => Ok(value == current_family()),
+                _ => Err(format!("Unknown cfg key {}", ident)),
+            }
+        }
+        _ => match ident.as_str() {
+            "unix" | "windows" => Ok(ident == current_family()),
+            "linux" | "android" | "macos" => Ok(ident == current_os()),
+            _ => Err(format!("Unknown cfg predicate {}", ident)),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_the_current_platform() {
+        let os = current_os();
+        let family = current_family();
+        assert_eq!(cfg_matches(&format!("cfg({})", family)), Ok(true));
+        assert_eq!(
+            cfg_matches(&format!("cfg(target_os = \"{}\")", os)),
+            Ok(true)
+        );
+        assert_eq!(
+            cfg_matches(&format!("cfg(target_family=\"{}\")", family)),
+            Ok(true)
+        );
+        assert_eq!(cfg_matches("cfg(target_os = \"plan9\")"), Ok(false));
+    }
+
+    #[test]
+    fn combines_nested_predicates() {
+        let os = current_os();
+        let yes = format!("target_os = \"{}\"", os);
+        let no = "target_os = \"plan9\"";
+        let cases = [
+            (format!("cfg(all({}, not({})))", yes, no), true),
+            (format!("cfg(any({}, {}))", no, yes), true),
+            (format!("cfg(all({}, {}))", yes, no), false),
+            (format!("cfg(not(any({}, all({}, {}))))", no, yes, no), true),
+            (format!("cfg(not(not({})))", yes), true),
+            //empty all is true and empty any is false, like in rust
+            ("cfg(all())".to_string(), true),
+            ("cfg(any())".to_string(), false),
+        ];
+        for (expr, expected) in cases {
+            assert_eq!(cfg_matches(&expr), Ok(expected), "{}", expr);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_expressions() {
+        for expr in [
+            "linux",
+            "cfg(linux",
+            "cfg()",
+            "cfg(linux windows)",
+            "cfg(linux,)",
+            "cfg(all(linux)",
+            "cfg(all(linux windows))",
+            "cfg(not(linux, windows))",
+            "cfg(not())",
+            "cfg(some(linux))",
+            "cfg(freebsd_like)",
+            "cfg(target_arch = \"x86\")",
+            "cfg(target_os = linux)",
+            "cfg(target_os = \"linux)",
+            "cfg(target_os =)",
+            "cfg(linux & windows)",
+        ] {
+            assert!(cfg_matches(expr).is_err(), "{} was accepted", expr);
+        }
+    }
+}
@@ -0,0 +1,543 @@
+use serde::de::{self, Deserializer, Visitor};
+use serde::Deserialize;
+use std::{collections::BTreeMap, ops::Range};
+use toml::Spanned;
+
+use super::log::{log, LogLevel};
+
+/// A config file as it is written, every table keeps its position in the file
+#[derive(Deserialize)]
+#[serde(expecting = "a table")]
+pub struct ConfigFile {
+    pub build: Option<Spanned<BuildTable>>,
+    pub targets: Option<Vec<Spanned<TargetTable>>>,
+    /// The profiles, by name
+    #[serde(default)]
+    pub profile: BTreeMap<String, Spanned<ProfileTable>>,
+    /// The platform specific sections of the targets, by cfg and then by target name
+    #[serde(default)]
+    pub target: BTreeMap<String, BTreeMap<String, Spanned<TargetTable>>>,
+}
+
+/// The build table
+#[derive(Deserialize, Default)]
+#[serde(expecting = "a table")]
+pub struct BuildTable {
+    pub cc: Option<String>,
+    pub cxx: Option<String>,
+    pub compiler: Option<String>,
+    #[serde(default)]
+    pub packages: Vec<String>,
+    pub cstandard: Option<String>,
+    pub cppstandard: Option<String>,
+    pub pre_build: Option<String>,
+    pub post_build: Option<String>,
+    #[serde(default)]
+    pub defines: BTreeMap<String, DefineValue>,
+}
+
+/// A profile table
+#[derive(Deserialize)]
+#[serde(expecting = "a table")]
+pub struct ProfileTable {
+    #[serde(default)]
+    pub cflags: String,
+    #[serde(default)]
+    pub ldflags: String,
+    #[serde(default)]
+    pub defines: BTreeMap<String, DefineValue>,
+}
+
+/// A target, or a platform specific section of one
+/// Every key is optional here, the keys a target needs are checked once the
+/// sections of the platform are applied
+#[derive(Deserialize)]
+#[serde(expecting = "a table")]
+pub struct TargetTable {
+    pub name: Option<Spanned<String>>,
+    #[serde(rename = "type")]
+    pub typ: Option<Spanned<String>>,
+    pub src: Option<StrList>,
+    pub sources: Option<StrList>,
+    pub exclude: Option<StrList>,
+    pub include_dir: Option<StrList>,
+    pub include_dirs: Option<StrList>,
+    pub public_include_dirs: Option<StrList>,
+    pub cflags: Option<String>,
+    pub libs: Option<String>,
+    pub deps: Option<Vec<String>>,
+    pub cstandard: Option<String>,
+    pub cppstandard: Option<String>,
+    pub defines: Option<BTreeMap<String, DefineValue>>,
+    pub max_jobs: Option<Spanned<usize>>,
+    pub pkg_config: Option<StrList>,
+}
+
+impl TargetTable {
+    /// Applies a platform specific section to the target
+    /// Flags and libs are appended, lists and defines are extended and any
+    /// other key is replaced
+    /// # Arguments
+    /// * `section` - The section of the platform
+    /// * `path` - The key path of the section, used in messages
+    /// * `diags` - Where errors are recorded
+    pub fn apply(&mut self, section: TargetTable, path: &str, diags: &mut Diagnostics) {
+        if let Some(name) = section.name {
+            diags.error(name.span(), path, "can not rename the target");
+        }
+        append_flags(&mut self.cflags, section.cflags);
+        append_flags(&mut self.libs, section.libs);
+        extend_list(&mut self.src, section.src);
+        extend_list(&mut self.sources, section.sources);
+        extend_list(&mut self.exclude, section.exclude);
+        extend_list(&mut self.include_dir, section.include_dir);
+        extend_list(&mut self.include_dirs, section.include_dirs);
+        extend_list(&mut self.public_include_dirs, section.public_include_dirs);
+        extend_list(&mut self.pkg_config, section.pkg_config);
+        if let Some(deps) = section.deps {
+            self.deps.get_or_insert_with(Vec::new).extend(deps);
+        }
+        if let Some(defines) = section.defines {
+            self.defines
+                .get_or_insert_with(BTreeMap::new)
+                .extend(defines);
+        }
+        self.typ = section.typ.or(self.typ.take());
+        self.cstandard = section.cstandard.or(self.cstandard.take());
+        self.cppstandard = section.cppstandard.or(self.cppstandard.take());
+        self.max_jobs = section.max_jobs.or(self.max_jobs.take());
+    }
+}
+
+//appends the flags of a platform section to those of the target
+fn append_flags(flags: &mut Option<String>, more: Option<String>) {
+    if let Some(more) = more {
+        *flags = Some(match flags.take() {
+            Some(flags) => format!("{} {}", flags, more),
+            None => more,
+        });
+    }
+}
+
+//extends a list of the target with the one of a platform section
+fn extend_list(list: &mut Option<StrList>, more: Option<StrList>) {
+    if let Some(more) = more {
+        let mut values = list.take().map(StrList::into_vec).unwrap_or_default();
+        values.extend(more.into_vec());
+        *list = Some(StrList::Many(values));
+    }
+}
+
+impl ConfigFile {
+    /// Parses a config file and warns about the keys that are not in the schema
+    /// Returns None if the file is not valid toml or a value has the wrong
+    /// type, which is recorded as an error
+    /// # Arguments
+    /// * `diags` - Holds the contents of the config file, errors are recorded in it
+    pub fn parse(diags: &mut Diagnostics) -> Option<ConfigFile> {
+        let mut unknown = Vec::new();
+        let mut on_unknown = |path: serde_ignored::Path| unknown.push(ignored_path(&path));
+        let deserializer = serde_ignored::Deserializer::new(
+            toml::Deserializer::new(diags.contents),
+            &mut on_unknown,
+        );
+        match serde_path_to_error::deserialize::<_, ConfigFile>(deserializer) {
+            Ok(config) => {
+                for path in &unknown {
+                    config.warn_unknown(path, diags);
+                }
+                Some(config)
+            }
+            Err(e) => {
+                let path = e
+                    .path()
+                    .iter()
+                    .filter_map(|segment| match segment {
+                        serde_path_to_error::Segment::Seq { index } => Some(Key::Index(*index)),
+                        serde_path_to_error::Segment::Map { key } => Key::name(key),
+                        _ => None,
+                    })
+                    .collect::<Vec<Key>>();
+                let e = e.into_inner();
+                //the parser puts what it expected on its own line
+                let message = e.message().trim().replace('\n', ", ");
+                diags.error(e.span().unwrap_or(0..0), &key_path(&path), &message);
+                None
+            }
+        }
+    }
+
+    //warns about a key that is not in the schema, pointing at it in its table
+    fn warn_unknown(&self, path: &[Key], diags: &Diagnostics) {
+        let Some((Key::Name(key), table_path)) = path.split_last() else {
+            return;
+        };
+        let (span, known) = self.table_at(table_path, diags.contents.len());
+        diags.warn(
+            find_key(diags.contents, span, key),
+            &key_path(path),
+            &unknown_key(key, known),
+        );
+    }
+
+    //returns the position of the table at a key path along with the keys it
+    //can have
+    fn table_at(&self, path: &[Key], len: usize) -> (Range<usize>, &'static [&'static str]) {
+        let span = |table: Option<Range<usize>>| table.unwrap_or(0..0);
+        match path {
+            [] => (0..len, keys_of::<ConfigFile>()),
+            [Key::Name(build)] if build == "build" => (
+                span(self.build.as_ref().map(Spanned::span)),
+                keys_of::<BuildTable>(),
+            ),
+            [Key::Name(targets), Key::Index(i)] if targets == "targets" => (
+                span(self.targets.iter().flatten().nth(*i).map(Spanned::span)),
+                keys_of::<TargetTable>(),
+            ),
+            [Key::Name(profile), Key::Name(name)] if profile == "profile" => (
+                span(self.profile.get(name).map(Spanned::span)),
+                keys_of::<ProfileTable>(),
+            ),
+            [Key::Name(target), Key::Name(cfg), Key::Name(name)] if target == "target" => (
+                span(
+                    self.target
+                        .get(cfg)
+                        .and_then(|sections| sections.get(name))
+                        .map(Spanned::span),
+                ),
+                keys_of::<TargetTable>(),
+            ),
+            _ => (0..0, &[]),
+        }
+    }
+}
+
+//a step of the key path of a value
+enum Key {
+    Name(String),
+    Index(usize),
+}
+
+impl Key {
+    //returns the step for a key, None for the keys toml uses internally, like
+    //the one toml::Spanned keeps the value under, as those are not in the file
+    fn name(key: &str) -> Option<Key> {
+        (!key.starts_with("$__")).then(|| Key::Name(key.to_string()))
+    }
+}
+
+//returns the key path serde_ignored found an unknown key at, ending with the key
+fn ignored_path(path: &serde_ignored::Path) -> Vec<Key> {
+    let (parent, key) = match path {
+        serde_ignored::Path::Root => return Vec::new(),
+        serde_ignored::Path::Seq { parent, index } => (parent, Some(Key::Index(*index))),
+        serde_ignored::Path::Map { parent, key } => (parent, Key::name(key)),
+        serde_ignored::Path::Some { parent }
+        | serde_ignored::Path::NewtypeStruct { parent }
+        | serde_ignored::Path::NewtypeVariant { parent } => (parent, None),
+    };
+    let mut keys = ignored_path(parent);
+    keys.extend(key);
+    keys
+}
+
+//returns a key path the way it is written in messages, like targets[0].name
+//or target.'cfg(unix)'.main
+fn key_path(path: &[Key]) -> String {
+    let mut written = String::new();
+    for key in path {
+        match key {
+            Key::Index(i) => written.push_str(&format!("[{}]", i)),
+            Key::Name(name) => {
+                if !written.is_empty() {
+                    written.push('.');
+                }
+                let bare = name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+                if bare && !name.is_empty() {
+                    written.push_str(name);
+                } else {
+                    written.push_str(&format!("'{}'", name));
+                }
+            }
+        }
+    }
+    written
+}
+
+//returns the position of a key of a table, a key that is not in the schema
+//is never read so it has no position of its own and is looked up in the text
+//of the table, falling back to the start of the table
+fn find_key(contents: &str, table: Range<usize>, key: &str) -> Range<usize> {
+    let mut line_start = table.start;
+    for line in contents[table.clone()].split_inclusive('\n') {
+        //the key can start a line of the table or a header below it
+        let rest = line.trim_start().trim_start_matches('[');
+        let key_start = line_start + line.len() - rest.len();
+        let after = rest.strip_prefix(key).map(str::trim_start);
+        if after.is_some_and(|after| after.starts_with(['=', '.', ']'])) {
+            return key_start..key_start + key.len();
+        }
+        line_start += line.len();
+    }
+    table.start..table.start
+}
+
+//returns the keys a table of the schema can have, which the derived
+//Deserialize hands to the deserializer when it asks for a struct
+fn keys_of<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    let mut keys: &'static [&'static str] = &[];
+    let _ = T::deserialize(KeysOf(&mut keys));
+    keys
+}
+
+//a deserializer that only records the keys of the struct asked of it
+struct KeysOf<'a>(&'a mut &'static [&'static str]);
+
+impl<'de> Deserializer<'de> for KeysOf<'_> {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("only a struct has keys"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        fields: &'static [&'static str],
+        _: V,
+    ) -> Result<V::Value, Self::Error> {
+        *self.0 = fields;
+        Err(de::Error::custom("the keys are all that is read"))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map enum identifier ignored_any
+    }
+}
+
+/// Returns the candidate closest to a misspelled name, if one is close enough
+/// to be a likely typo
+/// # Arguments
+/// * `name` - The misspelled name
+/// * `candidates` - The names that are valid
+pub fn closest_match<'a>(name: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    let max_distance = (name.chars().count() / 3).max(2);
+    candidates
+        .iter()
+        .map(|candidate| (edit_distance(name, candidate), *candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+//returns the number of single character edits that turn a into b
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<char>>();
+    let mut previous = (0..=b.len()).collect::<Vec<usize>>();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+//returns the warning for a key that is not in the schema
+fn unknown_key(key: &str, known: &[&str]) -> String {
+    match closest_match(key, known) {
+        Some(suggestion) => format!("unknown key, did you mean {}?", suggestion),
+        None => "unknown key".to_string(),
+    }
+}
+
+/// A key holding either a single string or a list of them
+#[derive(Deserialize)]
+#[serde(untagged, expecting = "expected a string or a list of strings")]
+pub enum StrList {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl StrList {
+    /// Returns the strings of the key
+    pub fn into_vec(self) -> Vec<String> {
+        match self {
+            StrList::One(value) => vec![value],
+            StrList::Many(values) => values,
+        }
+    }
+}
+
+/// The value of a preprocessor define
+#[derive(Deserialize)]
+#[serde(untagged, expecting = "expected a string, a number or a boolean")]
+pub enum DefineValue {
+    Str(String),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+}
+
+/// Collects the problems found in a config file so all of them are reported at once
+pub struct Diagnostics<'a> {
+    path: &'a str,
+    contents: &'a str,
+    errors: Vec<(usize, String)>,
+}
+
+impl<'a> Diagnostics<'a> {
+    /// Creates an empty set of diagnostics for a config file
+    /// # Arguments
+    /// * `path` - The path to the config file
+    /// * `contents` - The contents of the config file
+    pub fn new(path: &'a str, contents: &'a str) -> Self {
+        Diagnostics {
+            path,
+            contents,
+            errors: Vec::new(),
+        }
+    }
+
+    //returns the file, line and column of a position in the config file
+    fn location(&self, span: &Range<usize>) -> String {
+        let before = &self.contents[..span.start.min(self.contents.len())];
+        let line = before.matches('\n').count() + 1;
+        let column = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+        format!("{}:{}:{}", self.path, line, column)
+    }
+
+    //returns a problem along with where it is, a syntax error has no key
+    fn describe(&self, span: &Range<usize>, key: &str, message: &str) -> String {
+        match key {
+            "" => format!("{}: {}", self.location(span), message),
+            key => format!("{}: {}: {}", self.location(span), key, message),
+        }
+    }
+
+    /// Records an error
+    /// # Arguments
+    /// * `span` - Where in the file the error is
+    /// * `key` - The key path of the value in error
+    /// * `message` - What is wrong with it
+    pub fn error(&mut self, span: Range<usize>, key: &str, message: &str) {
+        let error = self.describe(&span, key, message);
+        self.errors.push((span.start, error));
+    }
+
+    /// Logs a warning
+    /// # Arguments
+    /// * `span` - Where in the file the problem is
+    /// * `key` - The key path of the value
+    /// * `message` - What is wrong with it
+    pub fn warn(&self, span: Range<usize>, key: &str, message: &str) {
+        log(LogLevel::Warn, &self.describe(&span, key, message));
+    }
+
+    /// Logs every error in the order they appear in the file and exits if
+    /// there were any
+    pub fn finish(mut self) {
+        if self.errors.is_empty() {
+            return;
+        }
+        self.errors.sort_by_key(|(start, _)| *start);
+        for (_, error) in &self.errors {
+            log(LogLevel::Error, error);
+        }
+        log(
+            LogLevel::Error,
+            &format!(
+                "Found {} error(s) in config file: {}",
+                self.errors.len(),
+                self.path
+            ),
+        );
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_single_character_edits() {
+        let cases = [
+            ("", "", 0),
+            ("libs", "libs", 0),
+            ("", "abc", 3),
+            ("abc", "", 3),
+            ("cflag", "cflags", 1),
+            ("libengin", "libengine", 1),
+            ("deps", "dpes", 2),
+            ("kitten", "sitting", 3),
+            ("größe", "grosse", 3),
+        ];
+        for (a, b, distance) in cases {
+            assert_eq!(edit_distance(a, b), distance, "{} → {}", a, b);
+            assert_eq!(edit_distance(b, a), distance, "{} → {}", b, a);
+        }
+    }
+
+    #[test]
+    fn suggests_the_closest_name() {
+        assert_eq!(
+            closest_match("cflag", keys_of::<TargetTable>()),
+            Some("cflags")
+        );
+        assert_eq!(
+            closest_match("includ_dir", keys_of::<TargetTable>()),
+            Some("include_dir")
+        );
+        assert_eq!(
+            closest_match("pakages", keys_of::<BuildTable>()),
+            Some("packages")
+        );
+        assert_eq!(
+            closest_match("libengin", &["libengine", "libgame", "main"]),
+            Some("libengine")
+        );
+        //the first of the closest names wins a tie
+        assert_eq!(closest_match("lib", &["liba", "libb"]), Some("liba"));
+    }
+
+    #[test]
+    fn suggests_nothing_for_distant_names() {
+        assert_eq!(closest_match("src", &[]), None);
+        assert_eq!(closest_match("xyz", &["libengine", "main"]), None);
+        //short names allow two edits, longer ones a third of their length
+        assert_eq!(closest_match("ab", &["abcd"]), Some("abcd"));
+        assert_eq!(closest_match("ab", &["abcde"]), None);
+        assert_eq!(closest_match("renderer", &["render"]), Some("render"));
+        assert_eq!(closest_match("renderer", &["rend"]), None);
+    }
+
+    #[test]
+    fn writes_key_paths_and_finds_unknown_keys() {
+        let path = [
+            Key::Name("target".to_string()),
+            Key::Name("cfg(unix)".to_string()),
+            Key::Name("main".to_string()),
+        ];
+        assert_eq!(key_path(&path), "target.'cfg(unix)'.main");
+        let path = [Key::Name("targets".to_string()), Key::Index(1)];
+        assert_eq!(key_path(&path), "targets[1]");
+
+        let contents = "[build]\ncc = \"gcc\"\n\n[[targets]]\nname = \"a\"\n  cflag = \"\"\n";
+        let table = contents.find("[[targets]]").unwrap()..contents.len();
+        let cflag = find_key(contents, table.clone(), "cflag");
+        assert_eq!(&contents[cflag], "cflag");
+        //a key the table does not have points at the table
+        assert_eq!(
+            find_key(contents, table.clone(), "libs"),
+            table.start..table.start
+        );
+        assert!(keys_of::<TargetTable>().contains(&"type"));
+    }
+}